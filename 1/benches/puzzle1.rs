@@ -0,0 +1,51 @@
+//! Day 1: parsing, both parts, and the scan of `Position::has_revisited`.
+//!
+//! Every day has its benchmarks in `N/benches`, the interpreter of days 12
+//! and 23 in `assembunny/benches`. To compare two commits, run
+//! `cargo bench -p puzzle1 -- --save-baseline before` on the first and
+//! `cargo bench -p puzzle1 -- --baseline before` on the second, with the
+//! package of the benchmark in place of `puzzle1`.
+
+#[macro_use]
+extern crate criterion;
+extern crate puzzle1;
+extern crate solution;
+
+use std::hint::black_box;
+
+use criterion::{BenchmarkId, Criterion};
+
+use puzzle1::{Day, Position};
+use solution::Solution;
+
+const INPUT: &str = include_str!("../input.txt");
+
+fn day(c: &mut Criterion) {
+    let commands = Day.parse(INPUT).unwrap();
+    let mut group = c.benchmark_group("day 1");
+    group.bench_function("parse", |b| b.iter(|| Day.parse(black_box(INPUT)).unwrap()));
+    group.bench_function("part 1", |b| b.iter(|| Day.part1(black_box(&commands)).unwrap()));
+    group.bench_function("part 2", |b| b.iter(|| Day.part2(black_box(&commands)).unwrap()));
+    group.finish();
+}
+
+/// `has_revisited` after walking the first commands of the input, by the
+/// number of positions visited so far.
+fn has_revisited(c: &mut Criterion) {
+    let commands = Day.parse(INPUT).unwrap();
+    let mut group = c.benchmark_group("day 1 has_revisited");
+    for &count in &[10, 50, commands.len()] {
+        let mut position = Position::new();
+        for &command in &commands[..count] {
+            position.apply_command(command);
+        }
+        let visited = position.visited_positions.len();
+        group.bench_with_input(BenchmarkId::from_parameter(visited), &position, |b, position| {
+            b.iter(|| black_box(position).has_revisited())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, day, has_revisited);
+criterion_main!(benches);
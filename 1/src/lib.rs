@@ -1,6 +1,10 @@
+extern crate solution;
+
 use std::str::FromStr;
 use std::error::Error;
 
+use solution::Solution;
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Direction {
     North,
@@ -22,18 +26,17 @@ pub struct Command {
 }
 
 impl FromStr for Command {
-    type Err = Box<Error>;
+    type Err = Box<dyn Error>;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (turn, distance) = s.split_at(1);
-        let turn = match turn {
-            "R" => Turn::Right,
-            "L" => Turn::Left,
-            _ => panic!("Unknown Turn: {} in command", turn),
+        let turn = match s.chars().next() {
+            Some('R') => Turn::Right,
+            Some('L') => Turn::Left,
+            _ => return Err(format!("Unknown Turn in command: {:?}", s).into()),
         };
-        let distance = distance.parse()?;
+        let distance = s[1..].parse()?;
 
-        Ok(Command{turn: turn, distance: distance})
+        Ok(Command{turn, distance})
     }
 }
 
@@ -75,6 +78,12 @@ pub struct Position {
     pub first_revisited_pos: Option<(i32, i32)>,
 }
 
+impl Default for Position {
+    fn default() -> Position {
+        Position::new()
+    }
+}
+
 impl Position {
     pub fn new() -> Position {
         Position {
@@ -114,7 +123,7 @@ impl Position {
     }
 
     pub fn has_revisited(&self) -> bool {
-        self.visited_positions.iter().any(|v| self.compare_x_y(&v))
+        self.visited_positions.iter().any(|v| self.compare_x_y(v))
     }
 
     pub fn compare_x_y(&self, other: &(i32, i32)) -> bool {
@@ -123,25 +132,49 @@ impl Position {
 }
 
 pub fn puzzle_part1(input: &str) -> i32 {
-    let mut position = Position::new();
-    for command in input.split(',') {
-        let command: Command = command.trim().parse().expect("parsing failed");
-        position.apply_command(command);
-    }
-    position.distance()
+    let commands = parse_commands(input).expect("parsing failed");
+    Day.part1(&commands).expect("part one always has an answer")
 }
 
+/// The distance to the first position visited twice, 0 if there is none.
 pub fn puzzle_part2(input: &str) -> i32 {
-    let mut position = Position::new();
-    for command in input.split(',') {
-        let command: Command = command.trim().parse().expect("parsing failed");
-        position.apply_command(command);
-        // check if we revisit a position
-        if let Some(first_revisited_pos) = position.first_revisited_pos {
-            return first_revisited_pos.0.abs() + first_revisited_pos.1.abs();
+    let commands = parse_commands(input).expect("parsing failed");
+    Day.part2(&commands).unwrap_or(0)
+}
+
+pub fn parse_commands(input: &str) -> Result<Vec<Command>, Box<dyn Error>> {
+    input.split(',').map(|command| command.trim().parse()).collect()
+}
+
+pub struct Day;
+
+impl Solution for Day {
+    type Input = Vec<Command>;
+    type Answer1 = i32;
+    type Answer2 = i32;
+
+    fn parse(&self, input: &str) -> Result<Vec<Command>, solution::Error> {
+        parse_commands(input)
+    }
+
+    fn part1(&self, commands: &Vec<Command>) -> Result<i32, solution::Error> {
+        let mut position = Position::new();
+        for &command in commands {
+            position.apply_command(command);
+        }
+        Ok(position.distance())
+    }
+
+    fn part2(&self, commands: &Vec<Command>) -> Result<i32, solution::Error> {
+        let mut position = Position::new();
+        for &command in commands {
+            position.apply_command(command);
+            if let Some((x, y)) = position.first_revisited_pos {
+                return Ok(x.abs() + y.abs());
+            }
         }
+        Err("No position is visited twice".into())
     }
-    0
 }
 
 
@@ -196,4 +229,14 @@ mod test {
         let position = puzzle_part2(input);
         assert_eq!(4, position);
     }
+
+    #[test]
+    fn solution() {
+        let commands = Day.parse("R8, R4, R4, R8").unwrap();
+        assert_eq!(Day.part1(&commands).unwrap(), 8);
+        assert_eq!(Day.part2(&commands).unwrap(), 4);
+        assert!(Day.part2(&Day.parse("R2, L3").unwrap()).is_err());
+        assert!(Day.parse("R2, X3").is_err());
+        assert!(Day.parse("").is_err());
+    }
 }
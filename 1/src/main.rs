@@ -1,9 +1,16 @@
+extern crate cli;
 extern crate puzzle1;
 
+use cli::Args;
 use puzzle1::{puzzle_part1, puzzle_part2};
 
 fn main() {
-    let input = include_str!("../input.txt");
-    println!("part one: {}", puzzle_part1(input));
-    println!("part two: {}", puzzle_part2(input));
+    let args = Args::from_env(&[]);
+    let input = args.input(include_str!("../input.txt"));
+    if args.runs(1) {
+        println!("part one: {}", puzzle_part1(&input));
+    }
+    if args.runs(2) {
+        println!("part two: {}", puzzle_part2(&input));
+    }
 }
@@ -0,0 +1,43 @@
+//! Day 12: parsing, both parts, and the ways to run the program.
+//!
+//! See `1/benches/puzzle1.rs` for comparing two commits.
+
+#[macro_use]
+extern crate criterion;
+extern crate puzzle12;
+extern crate solution;
+
+use std::hint::black_box;
+
+use criterion::Criterion;
+
+use puzzle12::{compiled, Day, Machine, Register};
+use solution::Solution;
+
+const INPUT: &str = include_str!("../input.txt");
+
+fn day(c: &mut Criterion) {
+    let code = Day::default().parse(INPUT).unwrap();
+    let mut group = c.benchmark_group("day 12");
+    group.bench_function("parse", |b| b.iter(|| Day::default().parse(black_box(INPUT)).unwrap()));
+    group.bench_function("part 1", |b| b.iter(|| Day::default().part1(black_box(&code)).unwrap()));
+    group.bench_function("part 2", |b| b.iter(|| Day::default().part2(black_box(&code)).unwrap()));
+    group.finish();
+}
+
+/// Part one without the optimizer the parts use.
+fn interpreter(c: &mut Criterion) {
+    let code = Day::default().parse(INPUT).unwrap();
+    let mut group = c.benchmark_group("day 12 interpreter");
+    group.sample_size(10);
+    group.bench_function("Machine", |b| b.iter(|| {
+        let mut machine = Machine::new(code.clone());
+        machine.run();
+        machine.get_reg(Register::A)
+    }));
+    group.bench_function("compiled", |b| b.iter(|| compiled::input(black_box([0; 4]))[0]));
+    group.finish();
+}
+
+criterion_group!(benches, day, interpreter);
+criterion_main!(benches);
@@ -0,0 +1,39 @@
+//! Translates `input.txt` and the programs in `programs/` into Rust functions.
+
+extern crate assembunny;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+
+fn translate(name: &str, path: &Path, out: &mut File) {
+    println!("cargo:rerun-if-changed={}", path.display());
+    let mut input = String::new();
+    File::open(path).and_then(|mut f| f.read_to_string(&mut input))
+        .unwrap_or_else(|e| panic!("Could not read {}: {}", path.display(), e));
+    let code = assembunny::InstructionSet::base().assemble(&input).unwrap_or_else(|errors| {
+        let messages: Vec<_> = errors.iter().map(|e| format!("{}: {}", path.display(), e)).collect();
+        panic!("Failed to parse program:\n{}", messages.join("\n"))
+    });
+    let source = assembunny::translate::translate(name, &code)
+        .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    writeln!(out, "{}", source).unwrap();
+}
+
+fn main() {
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let mut out = File::create(Path::new(&out_dir).join("programs.rs")).unwrap();
+
+    translate("input", Path::new("input.txt"), &mut out);
+    println!("cargo:rerun-if-changed=programs");
+    let mut programs: Vec<_> = fs::read_dir("programs").unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "txt"))
+        .collect();
+    programs.sort();
+    for path in programs {
+        let name = path.file_stem().unwrap().to_str().unwrap().to_string();
+        translate(&name, &path, &mut out);
+    }
+}
@@ -1,177 +1,64 @@
-use std::str::FromStr;
-
-pub fn puzzle(input: &str) -> i32 {
-    let code: Vec<_> = input.lines().enumerate().map(|(k,v)| {
-        Instruction::from_str(v).expect(&format!("Failed to parse line {}: {}", k, v))
-    }).collect();
-    let mut machine = Machine::new(code);
-    machine.run();
-    machine.get_reg(Register::A)
+extern crate assembunny;
+extern crate solution;
+
+use assembunny::{InstructionSet, Outcome, ParseErrors};
+use solution::Solution;
+pub use assembunny::{FromLocation, Instruction, Machine, MachineConfig, Register, Word};
+
+/// `input.txt` and the programs in `programs/`, translated to Rust by the build script.
+///
+/// Each function takes the initial registers a to d and returns them at the end.
+pub mod compiled {
+    include!(concat!(env!("OUT_DIR"), "/programs.rs"));
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub enum Register {
-    A,
-    B,
-    C,
-    D,
+pub fn puzzle(input: &str) -> Result<i64, solution::Error> {
+    puzzle_with_config(input, &MachineConfig::default().check_overflow(true))
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub enum FromLocation {
-    Int(i32),
-    Reg(Register),
+/// Runs the program from the configured state and returns register a, see
+/// `MachineConfig::run_program`.
+///
+/// Fails if the program doesn't assemble or doesn't finish.
+pub fn puzzle_with_config<W: Word>(input: &str, config: &MachineConfig<W>) -> Result<W, solution::Error> {
+    let code = InstructionSet::base().assemble(input).map_err(ParseErrors)?;
+    config.run_program(code).map_err(unfinished)
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub enum Instruction {
-    Inc(Register),
-    Dec(Register),
-    Cpy(FromLocation, Register),
-    Jnz(FromLocation, i32),
+fn unfinished(outcome: Outcome) -> solution::Error {
+    format!("Program did not finish: {:?}", outcome).into()
 }
 
-impl FromStr for Instruction {
-    type Err = String;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut parts = s.split_whitespace();
-        match parts.next() {
-            Some("cpy") => Ok(Instruction::Cpy(
-                    parts.next().ok_or("err")?.parse()?,
-                    parts.next().ok_or("err")?.parse()?)
-                ),
-            Some("inc") => Ok(Instruction::Inc(
-                    parts.next().ok_or("err")?.parse()?)
-                ),
-            Some("dec") => Ok(Instruction::Dec(
-                    parts.next().ok_or("err")?.parse()?)
-                ),
-            Some("jnz") => Ok(Instruction::Jnz(
-                    parts.next().ok_or("err")?.parse()?,
-                    parts.next().ok_or("err")?.parse().unwrap()
-                    )
-                ),
-
-            _ => Err("Not implemented".into()),
-        }
-    }
+/// Both parts, register c starts at `c` if set, else at 0 in part one and 1 in part two.
+///
+/// The program runs on `i64` registers and fails if one overflows.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Day {
+    pub c: Option<i64>,
 }
 
-impl FromStr for FromLocation {
-    type Err = String;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "a" => Ok(FromLocation::Reg(Register::A)),
-            "b" => Ok(FromLocation::Reg(Register::B)),
-            "c" => Ok(FromLocation::Reg(Register::C)),
-            "d" => Ok(FromLocation::Reg(Register::D)),
-            s => Ok(FromLocation::Int(s.parse().unwrap())),
-        }
+impl Day {
+    fn solve(&self, code: &[Instruction], default: i64) -> Result<i64, solution::Error> {
+        let config = MachineConfig::default().reg(Register::C, self.c.unwrap_or(default)).check_overflow(true);
+        config.run_program(code.to_vec()).map_err(unfinished)
     }
 }
 
-impl FromStr for Register {
-    type Err = String;
+impl Solution for Day {
+    type Input = Vec<Instruction>;
+    type Answer1 = i64;
+    type Answer2 = i64;
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "a" => Ok(Register::A),
-            "b" => Ok(Register::B),
-            "c" => Ok(Register::C),
-            "d" => Ok(Register::D),
-            _ => Err(format!("Invalid Register {}", s).into()),
-        }
+    fn parse(&self, input: &str) -> Result<Vec<Instruction>, solution::Error> {
+        Ok(InstructionSet::base().assemble(input).map_err(ParseErrors)?)
     }
-}
-
-
-#[derive(Debug, Default)]
-pub struct Machine {
-    reg_a: i32,
-    reg_b: i32,
-    reg_c: i32,
-    reg_d: i32,
-    pc: usize,
-    code: Vec<Instruction>,
-}
 
-impl Machine {
-
-    pub fn new(code: Vec<Instruction>) -> Machine {
-        Machine { code: code, ..Default::default() }
-    }
-
-    pub fn run(&mut self) {
-        while let Some(()) = self.execute() {
-        }
+    fn part1(&self, code: &Vec<Instruction>) -> Result<i64, solution::Error> {
+        self.solve(code, 0)
     }
 
-    pub fn execute(&mut self) -> Option<()> {
-        let inst = self.code[self.pc].clone();
-        match inst {
-            Instruction::Dec(reg) => self.decrement(reg),
-            Instruction::Inc(reg) => self.increment(reg),
-            Instruction::Cpy(src, dst) => self.copy(src, dst),
-            Instruction::Jnz(value, offset) => self.jnz(value, offset),
-        }
-        self.pc += 1;
-        if self.pc >= self.code.len() {
-            None
-        } else {
-            Some(())
-        }
-    }
-
-    pub fn get_reg(&mut self, reg: Register) -> i32 {
-        match reg {
-            Register::A => self.reg_a,
-            Register::B => self.reg_b,
-            Register::C => self.reg_c,
-            Register::D => self.reg_d,
-        }
-    }
-
-    pub fn get_reg_mut(&mut self, reg: Register) -> &mut i32 {
-        match reg {
-            Register::A => &mut self.reg_a,
-            Register::B => &mut self.reg_b,
-            Register::C => &mut self.reg_c,
-            Register::D => &mut self.reg_d,
-        }
-    }
-
-    pub fn jnz(&mut self, value: FromLocation, offset: i32) {
-        let value = match value {
-            FromLocation::Reg(reg) => self.get_reg(reg),
-            FromLocation::Int(val) => val,
-        };
-        if value != 0 {
-            if offset > 0 {
-                self.pc += offset as usize;
-            } else {
-                self.pc -= (-offset) as usize;
-            }
-            // pc will get autoincremented afterwards
-            self.pc -= 1;
-        }
-    }
-
-    pub fn copy(&mut self, src: FromLocation, dst: Register) {
-        let src = match src {
-            FromLocation::Int(i) => i,
-            FromLocation::Reg(reg) => self.get_reg(reg),
-        };
-        *self.get_reg_mut(dst) = src;
-    }
-
-    pub fn decrement(&mut self, reg: Register) {
-        *self.get_reg_mut(reg) -= 1;
-    }
-
-    pub fn increment(&mut self, reg: Register) {
-        *self.get_reg_mut(reg) += 1;
+    fn part2(&self, code: &Vec<Instruction>) -> Result<i64, solution::Error> {
+        self.solve(code, 1)
     }
 }
 
@@ -181,44 +68,56 @@ mod test {
     use super::*;
 
     #[test]
-    fn parse_cpy() {
-        let instruction: Instruction = "cpy 41 a".parse().unwrap();
-        assert_eq!(instruction, Instruction::Cpy(FromLocation::Int(41), Register::A));
+    fn sample() {
+        let input = include_str!("../programs/sample.txt");
+        assert_eq!(puzzle(input).unwrap(), 42);
+        assert_eq!(compiled::sample([0; 4])[0], 42);
     }
 
     #[test]
-    fn parse_inc() {
-        let instruction: Instruction = "inc a".parse().unwrap();
-        assert_eq!(instruction, Instruction::Inc(Register::A));
+    fn rejects_tgl() {
+        let code = InstructionSet::base().parse_program("cpy 1 a\ntgl a");
+        assert!(code.is_err());
     }
 
     #[test]
-    fn parse_dec() {
-        let instruction: Instruction = "dec a".parse().unwrap();
-        assert_eq!(instruction, Instruction::Dec(Register::A));
+    fn initial_registers() {
+        let input = include_str!("../programs/skip.txt");
+        assert_eq!(puzzle_with_config(input, &MachineConfig::new()).unwrap(), 2);
+        assert_eq!(puzzle_with_config(input, &MachineConfig::new().reg(Register::C, 1)).unwrap(), 1);
+        assert_eq!(compiled::skip([0; 4])[0], 2);
+        assert_eq!(compiled::skip([0, 0, 1, 0])[0], 1);
     }
 
     #[test]
-    fn parse_jnz() {
-        let instruction: Instruction = "jnz a 2".parse().unwrap();
-        assert_eq!(instruction, Instruction::Jnz(FromLocation::Reg(Register::A), 2));
+    fn compiled_input() {
+        let input = include_str!("../input.txt");
+        for c in 0..2 {
+            let config = MachineConfig::new().reg(Register::C, c);
+            assert_eq!(compiled::input([0, 0, c, 0])[0], puzzle_with_config(input, &config).unwrap());
+        }
     }
 
     #[test]
-    fn parse_jnz_2() {
-        let instruction: Instruction = "jnz 1 5".parse().unwrap();
-        assert_eq!(instruction, Instruction::Jnz(FromLocation::Int(1), 5));
+    fn step_limit() {
+        let config = MachineConfig::new().max_steps(100);
+        let error = puzzle_with_config("inc a\njnz 1 -1", &config).unwrap_err();
+        assert_eq!(error.to_string(), "Program did not finish: StepLimitReached");
+        assert!(puzzle("cpy 1 a\ntgl a").is_err());
     }
 
     #[test]
-    fn sample() {
-        let input =
-"cpy 41 a
-inc a
-inc a
-dec a
-jnz a 2
-dec a";
-        assert_eq!(puzzle(input), 42);
+    fn solution() {
+        let code = Day::default().parse(include_str!("../programs/skip.txt")).unwrap();
+        assert_eq!(Day::default().part1(&code).unwrap(), 2);
+        assert_eq!(Day::default().part2(&code).unwrap(), 1);
+        assert_eq!(Day { c: Some(1) }.part1(&code).unwrap(), 1);
+        assert!(Day::default().parse("cpy 1 a\ntgl a").is_err());
+        let looping = Day::default().parse("jnz 1 0").unwrap();
+        assert!(Day::default().part1(&looping).is_err());
+        let overflowing = Day::default().parse("inc a").unwrap();
+        assert!(Day { c: Some(0) }.part1(&overflowing).is_ok());
+        let overflowing = Day::default().parse("cpy c a\ninc a").unwrap();
+        assert!(Day { c: Some(i64::MAX) }.part1(&overflowing).is_err());
     }
 }
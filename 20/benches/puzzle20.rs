@@ -0,0 +1,41 @@
+//! Day 20: parsing, both parts, and the linear scans of the blacklist.
+//!
+//! See `1/benches/puzzle1.rs` for comparing two commits.
+
+#[macro_use]
+extern crate criterion;
+extern crate puzzle20;
+extern crate solution;
+
+use std::hint::black_box;
+
+use criterion::Criterion;
+
+use puzzle20::Day;
+use solution::Solution;
+
+const INPUT: &str = include_str!("../input.txt");
+
+fn day(c: &mut Criterion) {
+    let blacklist = Day.parse(INPUT).unwrap();
+    let mut group = c.benchmark_group("day 20");
+    // part one tries every IP below the answer
+    group.sample_size(10);
+    group.bench_function("parse", |b| b.iter(|| Day.parse(black_box(INPUT)).unwrap()));
+    group.bench_function("part 1", |b| b.iter(|| Day.part1(black_box(&blacklist)).unwrap()));
+    group.bench_function("part 2", |b| b.iter(|| Day.part2(black_box(&blacklist)).unwrap()));
+    group.finish();
+}
+
+fn blacklist(c: &mut Criterion) {
+    let blacklist = Day.parse(INPUT).unwrap();
+    let mut group = c.benchmark_group("day 20 blacklist");
+    group.sample_size(10);
+    group.bench_function("lowest_ip", |b| b.iter(|| black_box(&blacklist).lowest_ip()));
+    // an IP past every range, so all of them are checked
+    group.bench_function("is_blocked", |b| b.iter(|| black_box(&blacklist).is_blocked(black_box(u32::MAX))));
+    group.finish();
+}
+
+criterion_group!(benches, day, blacklist);
+criterion_main!(benches);
@@ -1,8 +1,12 @@
+extern crate solution;
+
 use std::str::FromStr;
 use std::error::Error;
 use std::fmt;
 use std::fmt::{Display, Formatter};
 
+use solution::Solution;
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct IpRange {
     pub start: u32,
@@ -16,15 +20,17 @@ impl Display for IpRange {
 }
 
 impl FromStr for IpRange {
-    type Err = Box<Error>;
+    type Err = Box<dyn Error>;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut parts = s.split('-');
         let range = IpRange {
-            start: parts.next().expect("Not enough tokens").parse()?,
-            end: parts.next().expect("Not enough tokens").parse()?
+            start: parts.next().ok_or("Not enough tokens")?.parse()?,
+            end: parts.next().ok_or("Not enough tokens")?.parse()?
         };
-        assert!(range.start < range.end);
+        if range.start > range.end {
+            return Err(format!("Range ends before it starts: {}", s).into());
+        }
         Ok(range)
     }
 }
@@ -50,19 +56,19 @@ impl Display for Blacklist {
 }
 
 impl FromStr for Blacklist {
-    type Err = Box<Error>;
+    type Err = Box<dyn Error>;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut list: Vec<IpRange> = s.lines().enumerate().map(|(line_number, line)| {
+        let mut list = s.lines().enumerate().map(|(line_number, line)| {
             IpRange::from_str(line)
-                .expect(&format!("Failed to parse line {}: {}", line_number, line))
-        }).collect();
+                .map_err(|e| format!("Failed to parse line {}: {}: {}", line_number, line, e).into())
+        }).collect::<Result<Vec<IpRange>, Self::Err>>()?;
         list.sort();
 
         // reduce list
         let list = Blacklist::reduce_list(list);
 
-        Ok(Blacklist{list: list})
+        Ok(Blacklist{list})
     }
 }
 
@@ -70,7 +76,7 @@ impl Blacklist {
 
     fn reduce_list(mut list: Vec<IpRange>) -> Vec<IpRange> {
         let mut i = 0;
-        while i < list.len()-1 {
+        while i + 1 < list.len() {
             // next list is completly included
             if list[i+1].start >= list[i].start && list[i+1].end <= list[i].end {
                 list.remove(i+1);
@@ -91,17 +97,41 @@ impl Blacklist {
 
     pub fn number_of_allowed_ips(&self) -> usize {
         let number_of_filtered_ips = self.list.iter()
-            .fold(0, |acc, ref x| acc + (x.end - x.start + 1));
-        4294967296 - (number_of_filtered_ips as usize)
+            .fold(0, |acc, x| acc + (x.end - x.start) as usize + 1);
+        4294967296 - number_of_filtered_ips
     }
 
     pub fn lowest_ip(&self) -> u32 {
 
-        let lowest = (0..4294967295).filter(|v| !self.is_blocked(*v)).next();
+        let lowest = (0..=u32::MAX).find(|v| !self.is_blocked(*v));
         lowest.expect("no unblocked IP found")
     }
 }
 
+pub struct Day;
+
+impl Solution for Day {
+    type Input = Blacklist;
+    type Answer1 = u32;
+    type Answer2 = usize;
+
+    fn parse(&self, input: &str) -> Result<Blacklist, solution::Error> {
+        input.parse()
+    }
+
+    fn part1(&self, blacklist: &Blacklist) -> Result<u32, solution::Error> {
+        if blacklist.number_of_allowed_ips() == 0 {
+            return Err("Every IP is blocked".into());
+        }
+        Ok(blacklist.lowest_ip())
+    }
+
+    fn part2(&self, blacklist: &Blacklist) -> Result<usize, solution::Error> {
+        Ok(blacklist.number_of_allowed_ips())
+    }
+}
+
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -111,6 +141,8 @@ mod test {
         let input = "5-8";
         let range: IpRange = input.parse().unwrap();
         assert_eq!(range, IpRange{start: 5, end: 8});
+        assert_eq!("5-5".parse::<IpRange>().unwrap(), IpRange{start: 5, end: 5});
+        assert!("8-5".parse::<IpRange>().is_err());
     }
 
     #[test]
@@ -148,4 +180,16 @@ mod test {
         let number_of_allowed_ips = blacklist.number_of_allowed_ips();
         assert_eq!(number_of_allowed_ips, 4294967296 - 8);
     }
+
+    #[test]
+    fn solution() {
+        let blacklist = Day.parse("5-8\n0-2\n4-7").unwrap();
+        assert_eq!(Day.part1(&blacklist).unwrap(), 3);
+        assert_eq!(Day.part2(&blacklist).unwrap(), 4294967296 - 8);
+        assert!(Day.part1(&Day.parse("0-4294967295").unwrap()).is_err());
+        assert!(Day.parse("5-8\n0").is_err());
+        assert!(Day.parse("5-8\n8-5").is_err());
+        assert_eq!(Day.part2(&Day.parse("5-5").unwrap()).unwrap(), 4294967296 - 1);
+        assert_eq!(Day.part1(&Day.parse("").unwrap()).unwrap(), 0);
+    }
 }
@@ -1,14 +1,21 @@
+extern crate cli;
 extern crate puzzle20;
 
+use cli::Args;
 use puzzle20::Blacklist;
 
 fn main() {
-    let input = include_str!("../input.txt");
+    let args = Args::from_env(&[]);
+    let input = args.input(include_str!("../input.txt"));
     let blacklist: Blacklist = input.parse().expect("Could not parse input");
 
-    let lowest_ip = blacklist.lowest_ip();
-    println!("part one: {}", lowest_ip);
+    if args.runs(1) {
+        let lowest_ip = blacklist.lowest_ip();
+        println!("part one: {}", lowest_ip);
+    }
 
-    let number_of_allowed_ips = blacklist.number_of_allowed_ips();
-    println!("part two: {}", number_of_allowed_ips);
+    if args.runs(2) {
+        let number_of_allowed_ips = blacklist.number_of_allowed_ips();
+        println!("part two: {}", number_of_allowed_ips);
+    }
 }
@@ -0,0 +1,40 @@
+//! Day 22: parsing, part one, and the loop over all pairs of nodes.
+//!
+//! See `1/benches/puzzle1.rs` for comparing two commits.
+
+#[macro_use]
+extern crate criterion;
+extern crate puzzle22;
+extern crate solution;
+
+use std::hint::black_box;
+
+use criterion::{BenchmarkId, Criterion};
+
+use puzzle22::{number_of_viable_pairs, Day};
+use solution::Solution;
+
+const INPUT: &str = include_str!("../input.txt");
+
+fn day(c: &mut Criterion) {
+    let nodes = Day.parse(INPUT).unwrap();
+    let mut group = c.benchmark_group("day 22");
+    group.bench_function("parse", |b| b.iter(|| Day.parse(black_box(INPUT)).unwrap()));
+    group.bench_function("part 1", |b| b.iter(|| Day.part1(black_box(&nodes)).unwrap()));
+    group.finish();
+}
+
+/// `number_of_viable_pairs` on the first nodes of the input, by the number of nodes.
+fn viable_pairs(c: &mut Criterion) {
+    let nodes = Day.parse(INPUT).unwrap();
+    let mut group = c.benchmark_group("day 22 viable pairs");
+    for &count in &[nodes.len() / 4, nodes.len() / 2, nodes.len()] {
+        group.bench_with_input(BenchmarkId::from_parameter(count), &nodes[..count], |b, nodes| {
+            b.iter(|| number_of_viable_pairs(black_box(nodes)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, day, viable_pairs);
+criterion_main!(benches);
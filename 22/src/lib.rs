@@ -1,28 +1,33 @@
+extern crate solution;
+
 use std::str::FromStr;
 use std::error::Error;
 
+use solution::Solution;
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct Node {
     pub used_tb: u32,
     pub free_tb: u32,
 }
 
-pub fn parse_size(s: &str) -> Result<u32, Box<Error>> {
-    let size: u32 = s[0..s.len()-1].parse()?;
-    assert!(s.ends_with('T'));
+pub fn parse_size(s: &str) -> Result<u32, Box<dyn Error>> {
+    let size: u32 = s.strip_suffix('T').ok_or_else(|| format!("Size not in T: {}", s))?.parse()?;
     Ok(size)
 }
 
 impl FromStr for Node {
-    type Err = Box<Error>;
+    type Err = Box<dyn Error>;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut tokens = s.split_whitespace().skip(1);
-        let size: u32 = parse_size((tokens.next().ok_or("Not enough token")?))?;
-        let used: u32 = parse_size((tokens.next().ok_or("Not enough token")?))?;
-        let free: u32 = parse_size((tokens.next().ok_or("Not enough token")?))?;
+        let size: u32 = parse_size(tokens.next().ok_or("Not enough token")?)?;
+        let used: u32 = parse_size(tokens.next().ok_or("Not enough token")?)?;
+        let free: u32 = parse_size(tokens.next().ok_or("Not enough token")?)?;
 
-        assert_eq!(used + free, size);
+        if used.checked_add(free) != Some(size) {
+            return Err(format!("Used and available don't add up to the size: {}", s).into());
+        }
         Ok(Node{
             used_tb: used,
             free_tb: free,
@@ -34,11 +39,14 @@ pub fn is_viable_pair(left: &Node, right: &Node) -> bool {
     left.used_tb != 0 && left.used_tb <= right.free_tb
 }
 
-pub fn puzzle(input: &str) -> usize {
-    let nodes: Vec<Node> = input.lines().skip(2).map(|v| {
-        v.parse().expect(&format!("Failed to parse line {}", v))
-    }).collect();
+/// Parses the output of `df`, skipping the command line and the header.
+pub fn parse_nodes(input: &str) -> Result<Vec<Node>, Box<dyn Error>> {
+    input.lines().skip(2).map(|v| {
+        v.parse().map_err(|e| format!("Failed to parse line {}: {}", v, e).into())
+    }).collect()
+}
 
+pub fn number_of_viable_pairs(nodes: &[Node]) -> usize {
     let mut number_of_viable_pairs: usize = 0;
     for node in nodes.iter() {
         number_of_viable_pairs += nodes.iter().filter(|b| is_viable_pair(node, b)).count();
@@ -50,6 +58,33 @@ pub fn puzzle(input: &str) -> usize {
     number_of_viable_pairs
 }
 
+pub fn puzzle(input: &str) -> usize {
+    number_of_viable_pairs(&parse_nodes(input).expect("parsing failed"))
+}
+
+/// Only part one is solved.
+pub struct Day;
+
+impl Solution for Day {
+    type Input = Vec<Node>;
+    type Answer1 = usize;
+    type Answer2 = usize;
+
+    const PARTS: u32 = 1;
+
+    fn parse(&self, input: &str) -> Result<Vec<Node>, solution::Error> {
+        parse_nodes(input)
+    }
+
+    fn part1(&self, nodes: &Vec<Node>) -> Result<usize, solution::Error> {
+        Ok(number_of_viable_pairs(nodes))
+    }
+
+    fn part2(&self, _: &Vec<Node>) -> Result<usize, solution::Error> {
+        Err("Part two is not solved".into())
+    }
+}
+
 
 #[cfg(test)]
 mod test {
@@ -60,5 +95,22 @@ mod test {
         let input = "/dev/grid/node-x0-y0     89T   67T    22T   75%";
         let node: Node = input.parse().unwrap();
         assert_eq!(node, Node{used_tb: 67, free_tb: 22});
+        assert!("/dev/grid/node-x0-y0     89T   67T    22G   75%".parse::<Node>().is_err());
+        assert!("/dev/grid/node-x0-y0     89T   67T    23T   75%".parse::<Node>().is_err());
+        assert!("/dev/grid/node-x0-y0     89T   4294967295T    2T   75%".parse::<Node>().is_err());
+        assert!(parse_size("").is_err());
+    }
+
+    #[test]
+    fn solution() {
+        let input = "root@ebhq-gridcenter# df -h\n\
+                     Filesystem              Size  Used  Avail  Use%\n\
+                     /dev/grid/node-x0-y0     10T    8T     2T   80%\n\
+                     /dev/grid/node-x0-y1     11T    6T     5T   54%\n\
+                     /dev/grid/node-x1-y0     32T    0T    32T    0%";
+        let nodes = Day.parse(input).unwrap();
+        assert_eq!(Day.part1(&nodes).unwrap(), 2);
+        assert!(Day.part2(&nodes).is_err());
+        assert!(Day.parse("df\nheader\n/dev/grid/node-x0-y0 10T").is_err());
     }
 }
@@ -1,9 +1,18 @@
+extern crate cli;
 extern crate puzzle22;
 
+use std::process;
+
+use cli::Args;
 use puzzle22::*;
 
 fn main() {
-    let input = include_str!("../input.txt");
-    let viable_pairs = puzzle(input);
+    let args = Args::from_env(&[]);
+    if args.part() == Some(2) {
+        eprintln!("Part two is not solved yet");
+        process::exit(1);
+    }
+    let input = args.input(include_str!("../input.txt"));
+    let viable_pairs = puzzle(&input);
     println!("{}", viable_pairs);
 }
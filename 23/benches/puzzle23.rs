@@ -0,0 +1,29 @@
+//! Day 23: parsing and both parts, see `assembunny/benches/dispatch.rs` for the interpreter.
+//!
+//! See `1/benches/puzzle1.rs` for comparing two commits.
+
+#[macro_use]
+extern crate criterion;
+extern crate puzzle23;
+extern crate solution;
+
+use std::hint::black_box;
+
+use criterion::Criterion;
+
+use puzzle23::Day;
+use solution::Solution;
+
+const INPUT: &str = include_str!("../input.txt");
+
+fn day(c: &mut Criterion) {
+    let code = Day::default().parse(INPUT).unwrap();
+    let mut group = c.benchmark_group("day 23");
+    group.bench_function("parse", |b| b.iter(|| Day::default().parse(black_box(INPUT)).unwrap()));
+    group.bench_function("part 1", |b| b.iter(|| Day::default().part1(black_box(&code)).unwrap()));
+    group.bench_function("part 2", |b| b.iter(|| Day::default().part2(black_box(&code)).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, day);
+criterion_main!(benches);
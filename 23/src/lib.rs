@@ -1,221 +1,60 @@
-use std::str::FromStr;
-
-pub fn puzzle(input: &str, reg_a_start_value: i32) -> i32 {
-    let code: Vec<_> = input.lines().enumerate().map(|(k,v)| {
-        Instruction::from_str(v).expect(&format!("Failed to parse line {}: {}", k, v))
-    }).collect();
-    let mut machine = Machine::new(code);
-    *machine.get_reg_mut(Register::A) = reg_a_start_value;
-    machine.run();
-    machine.get_reg(Register::A)
+extern crate assembunny;
+extern crate solution;
+
+use assembunny::{InstructionSet, Outcome, ParseErrors};
+use solution::Solution;
+pub use assembunny::{FromLocation, Instruction, Machine, MachineConfig, Register, Word};
+
+/// Runs the program with register a set to `reg_a_start_value`.
+///
+/// The type of the start value picks the register width of the machine.
+pub fn puzzle<W: Word>(input: &str, reg_a_start_value: W) -> Result<W, solution::Error> {
+    puzzle_with_config(input, &MachineConfig::default().reg(Register::A, reg_a_start_value))
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub enum Register {
-    A,
-    B,
-    C,
-    D,
+/// Runs the program from the configured state and returns register a, see
+/// `MachineConfig::run_program`.
+///
+/// Fails if the program doesn't assemble or doesn't finish.
+pub fn puzzle_with_config<W: Word>(input: &str, config: &MachineConfig<W>) -> Result<W, solution::Error> {
+    let code = InstructionSet::base().with_tgl().assemble(input).map_err(ParseErrors)?;
+    config.run_program(code).map_err(unfinished)
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub enum FromLocation {
-    Int(i32),
-    Reg(Register),
+fn unfinished(outcome: Outcome) -> solution::Error {
+    format!("Program did not finish: {:?}", outcome).into()
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub enum Instruction {
-    Inc(Register),
-    Dec(Register),
-    Cpy(FromLocation, Register),
-    Jnz(FromLocation, FromLocation),
-    Tgl(FromLocation),
-    Invalid,
+/// Both parts, register a starts at `a` if set, else at 7 in part one and 12 in part two.
+///
+/// The program runs on `i64` registers and fails if one overflows.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Day {
+    pub a: Option<i64>,
 }
 
-impl FromStr for Instruction {
-    type Err = String;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut parts = s.split_whitespace();
-        match parts.next() {
-            Some("cpy") => Ok(Instruction::Cpy(
-                    parts.next().ok_or("err")?.parse()?,
-                    parts.next().ok_or("err")?.parse()?)
-                ),
-            Some("inc") => Ok(Instruction::Inc(
-                    parts.next().ok_or("err")?.parse()?)
-                ),
-            Some("dec") => Ok(Instruction::Dec(
-                    parts.next().ok_or("err")?.parse()?)
-                ),
-            Some("jnz") => Ok(Instruction::Jnz(
-                    parts.next().ok_or("err")?.parse()?,
-                    parts.next().ok_or("err")?.parse().unwrap()
-                    )
-                ),
-            Some("tgl") => Ok(Instruction::Tgl(
-                    parts.next().ok_or("err")?.parse()?,
-                    )
-                ),
-
-            _ => Err("Not implemented".into()),
-        }
+impl Day {
+    fn solve(&self, code: &[Instruction], default: i64) -> Result<i64, solution::Error> {
+        let config = MachineConfig::default().reg(Register::A, self.a.unwrap_or(default)).check_overflow(true);
+        config.run_program(code.to_vec()).map_err(unfinished)
     }
 }
 
-impl FromStr for FromLocation {
-    type Err = String;
+impl Solution for Day {
+    type Input = Vec<Instruction>;
+    type Answer1 = i64;
+    type Answer2 = i64;
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "a" => Ok(FromLocation::Reg(Register::A)),
-            "b" => Ok(FromLocation::Reg(Register::B)),
-            "c" => Ok(FromLocation::Reg(Register::C)),
-            "d" => Ok(FromLocation::Reg(Register::D)),
-            s => Ok(FromLocation::Int(s.parse().unwrap())),
-        }
+    fn parse(&self, input: &str) -> Result<Vec<Instruction>, solution::Error> {
+        Ok(InstructionSet::base().with_tgl().assemble(input).map_err(ParseErrors)?)
     }
-}
-
-impl FromStr for Register {
-    type Err = String;
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "a" => Ok(Register::A),
-            "b" => Ok(Register::B),
-            "c" => Ok(Register::C),
-            "d" => Ok(Register::D),
-            _ => Err(format!("Invalid Register {}", s).into()),
-        }
+    fn part1(&self, code: &Vec<Instruction>) -> Result<i64, solution::Error> {
+        self.solve(code, 7)
     }
-}
-
-
-#[derive(Debug, Default)]
-pub struct Machine {
-    reg_a: i32,
-    reg_b: i32,
-    reg_c: i32,
-    reg_d: i32,
-    pc: usize,
-    code: Vec<Instruction>,
-}
 
-impl Machine {
-
-    pub fn new(code: Vec<Instruction>) -> Machine {
-        Machine { code: code, ..Default::default() }
-    }
-
-    pub fn run(&mut self) {
-        while let Some(()) = self.execute() {
-        }
-    }
-
-    pub fn execute(&mut self) -> Option<()> {
-        let inst = self.code[self.pc].clone();
-        match inst {
-            Instruction::Dec(reg) => self.decrement(reg),
-            Instruction::Inc(reg) => self.increment(reg),
-            Instruction::Cpy(src, dst) => self.copy(src, dst),
-            Instruction::Jnz(value, FromLocation::Int(offset)) => self.jnz(value, offset),
-            Instruction::Jnz(value, FromLocation::Reg(reg)) => {
-                let offset = self.get_reg(reg);
-                self.jnz(value, offset)
-            }
-            Instruction::Invalid => (),
-            Instruction::Tgl(FromLocation::Int(offset)) => self.toggle(offset),
-            Instruction::Tgl(FromLocation::Reg(reg)) => {
-                let reg = self.get_reg(reg);
-                self.toggle(reg)
-            }
-        }
-        self.pc += 1;
-        if self.pc >= self.code.len() {
-            None
-        } else {
-            Some(())
-        }
-    }
-
-    pub fn toggle(&mut self, offset: i32) {
-        let position = if offset > 0 {
-            self.pc + offset as usize
-        } else {
-            self.pc - (-offset) as usize
-        };
-        if position >= self.code.len() {
-            return
-        }
-        self.code[position] = Self::toggle_instruction(self.code[position].clone());
-    }
-
-    pub fn toggle_instruction(instruction: Instruction) -> Instruction {
-        match instruction {
-            Instruction::Inc(reg) => Instruction::Dec(reg),
-            Instruction::Dec(reg) | Instruction::Tgl(FromLocation::Reg(reg)) => Instruction::Inc(reg),
-            //Instruction::Tgl(FromLocation::Int(_)) => Instruction::Invalid,
-
-            //Instruction::Jnz(FromLocation::Int(_), FromLocation::Int(_)) => Instruction::Invalid,
-            Instruction::Jnz(from, FromLocation::Reg(reg)) => Instruction::Cpy(from, reg),
-
-            Instruction::Cpy(from, reg) => Instruction::Jnz(from, FromLocation::Reg(reg)),
-            //Instruction::Invalid => Instruction::Invalid,
-            _ => Instruction::Invalid,
-        }
-    }
-
-    pub fn get_reg(&self, reg: Register) -> i32 {
-        match reg {
-            Register::A => self.reg_a,
-            Register::B => self.reg_b,
-            Register::C => self.reg_c,
-            Register::D => self.reg_d,
-        }
-    }
-
-    pub fn get_reg_mut(&mut self, reg: Register) -> &mut i32 {
-        match reg {
-            Register::A => &mut self.reg_a,
-            Register::B => &mut self.reg_b,
-            Register::C => &mut self.reg_c,
-            Register::D => &mut self.reg_d,
-        }
-    }
-
-    pub fn jnz(&mut self, value: FromLocation, offset: i32) {
-        let value = match value {
-            FromLocation::Reg(reg) => self.get_reg(reg),
-            FromLocation::Int(val) => val,
-        };
-        if value != 0 {
-            if offset > 0 {
-                self.pc += offset as usize;
-            } else {
-                self.pc -= (-offset) as usize;
-            }
-            // pc will get autoincremented afterwards
-            self.pc -= 1;
-        }
-    }
-
-    pub fn copy(&mut self, src: FromLocation, dst: Register) {
-        let src = match src {
-            FromLocation::Int(i) => i,
-            FromLocation::Reg(reg) => self.get_reg(reg),
-        };
-        *self.get_reg_mut(dst) = src;
-    }
-
-    pub fn decrement(&mut self, reg: Register) {
-        *self.get_reg_mut(reg) -= 1;
-    }
-
-    pub fn increment(&mut self, reg: Register) {
-        *self.get_reg_mut(reg) += 1;
+    fn part2(&self, code: &Vec<Instruction>) -> Result<i64, solution::Error> {
+        self.solve(code, 12)
     }
 }
 
@@ -223,36 +62,7 @@ impl Machine {
 #[cfg(test)]
 mod test {
     use super::*;
-
-    #[test]
-    fn parse_cpy() {
-        let instruction: Instruction = "cpy 41 a".parse().unwrap();
-        assert_eq!(instruction, Instruction::Cpy(FromLocation::Int(41), Register::A));
-    }
-
-    #[test]
-    fn parse_inc() {
-        let instruction: Instruction = "inc a".parse().unwrap();
-        assert_eq!(instruction, Instruction::Inc(Register::A));
-    }
-
-    #[test]
-    fn parse_dec() {
-        let instruction: Instruction = "dec a".parse().unwrap();
-        assert_eq!(instruction, Instruction::Dec(Register::A));
-    }
-
-    #[test]
-    fn parse_jnz() {
-        let instruction: Instruction = "jnz a 2".parse().unwrap();
-        assert_eq!(instruction, Instruction::Jnz(FromLocation::Reg(Register::A), FromLocation::Int(2)));
-    }
-
-    #[test]
-    fn parse_jnz_2() {
-        let instruction: Instruction = "jnz 1 5".parse().unwrap();
-        assert_eq!(instruction, Instruction::Jnz(FromLocation::Int(1), FromLocation::Int(5)));
-    }
+    use assembunny::Halt;
 
     #[test]
     fn sample() {
@@ -263,19 +73,7 @@ inc a
 dec a
 jnz a 2
 dec a";
-        assert_eq!(puzzle(input, 0), 42);
-    }
-
-    #[test]
-    fn test_toggle_instruction_inc() {
-        let instruction = Instruction::Inc(Register::A);
-        assert_eq!(Machine::toggle_instruction(instruction), Instruction::Dec(Register::A));
-    }
-
-    #[test]
-    fn test_toggle_instruction_dec() {
-        let instruction = Instruction::Dec(Register::A);
-        assert_eq!(Machine::toggle_instruction(instruction), Instruction::Inc(Register::A));
+        assert_eq!(puzzle(input, 0).unwrap(), 42);
     }
 
     #[test]
@@ -288,6 +86,47 @@ tgl a
 cpy 1 a
 dec a
 dec a";
-        assert_eq!(puzzle(input, 0), 3);
+        assert_eq!(puzzle(input, 0).unwrap(), 3);
+    }
+
+    #[test]
+    fn optimizer_matches_plain_run() {
+        let input = include_str!("../input.txt");
+        let code = InstructionSet::base().with_tgl().parse_program(input).unwrap();
+        let mut machine = Machine::new(code);
+        *machine.get_reg_mut(Register::A) = 7;
+        machine.run();
+        assert_eq!(puzzle(input, 7).unwrap(), machine.get_reg(Register::A));
+    }
+
+    #[test]
+    fn wide_registers() {
+        let input = include_str!("../input.txt");
+        // 13! doesn't fit into an i32
+        assert_eq!(puzzle(input, 13i64).unwrap(), 6227020800 + 83 * 78);
+    }
+
+    #[test]
+    fn overflow_check() {
+        let input = include_str!("../input.txt");
+        let config = MachineConfig::new().reg(Register::A, 13).check_overflow(true);
+        let error = puzzle_with_config(input, &config).unwrap_err();
+        let outcome = Outcome::Halted(Halt::Overflow { pc: 4 });
+        assert_eq!(error.to_string(), format!("Program did not finish: {:?}", outcome));
+        assert!(puzzle("mul a 2", 0).is_err());
+    }
+
+    #[test]
+    fn solution() {
+        let code = Day::default().parse("cpy 2 a\ntgl a\ntgl a\ntgl a\ncpy 1 a\ndec a\ndec a").unwrap();
+        assert_eq!(Day::default().part1(&code).unwrap(), 3);
+        let code = Day::default().parse("inc a").unwrap();
+        assert_eq!(Day::default().part1(&code).unwrap(), 8);
+        assert_eq!(Day::default().part2(&code).unwrap(), 13);
+        assert_eq!(Day { a: Some(0) }.part2(&code).unwrap(), 1);
+        let code = Day::default().parse(include_str!("../input.txt")).unwrap();
+        assert_eq!(Day { a: Some(13) }.part1(&code).unwrap(), 6227020800 + 83 * 78);
+        assert!(Day { a: Some(21) }.part1(&code).is_err());
+        assert!(Day::default().parse("mul a 2").is_err());
     }
 }
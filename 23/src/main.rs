@@ -1,10 +1,11 @@
+extern crate cli;
 extern crate puzzle23;
 
-fn main() {
-    let input = include_str!("../input.txt");
-    let result = puzzle23::puzzle(input, 7);
-    println!("part one: {}", result);
+use cli::Args;
+use puzzle23::Day;
 
-    let result = puzzle23::puzzle(input, 12);
-    println!("part two: {}", result);
+fn main() {
+    // the initial value of register a, 7 for part one and 12 for part two
+    let args = Args::from_env(&["a"]);
+    args.run(&Day { a: args.param("a") }, include_str!("../input.txt"));
 }
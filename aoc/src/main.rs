@@ -0,0 +1,239 @@
+//! Runs the puzzles of every day: `aoc run <day>|all [--part 1|2] [input|-]`.
+//!
+//! Options of a single day, like `--a` for day 23, are passed on to it. The
+//! wall-clock time of every part is reported next to its answer.
+//!
+//! `aoc verify <day>|all [--part 1|2]` compares the answers to the bundled
+//! inputs with `answers.txt` in the directory of the day, which holds one
+//! answer per line, in the order of the parts. Days without the file are
+//! skipped.
+
+extern crate cli;
+extern crate puzzle1;
+extern crate puzzle12;
+extern crate puzzle20;
+extern crate puzzle22;
+extern crate puzzle23;
+extern crate solution;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::process;
+use std::time::Instant;
+
+use cli::Args;
+use solution::Puzzle;
+
+/// A day the runner knows.
+struct Day {
+    number: u32,
+    input: &'static str,
+    /// The options it accepts besides `--part`.
+    params: &'static [&'static str],
+    /// Its solution, set up with the options given.
+    solution: fn(&Args) -> Result<Box<dyn Puzzle>, String>,
+}
+
+static DAYS: [Day; 5] = [
+    Day { number: 1, input: include_str!("../../1/input.txt"), params: &[], solution: day1 },
+    Day { number: 12, input: include_str!("../../12/input.txt"), params: &["c"], solution: day12 },
+    Day { number: 20, input: include_str!("../../20/input.txt"), params: &[], solution: day20 },
+    Day { number: 22, input: include_str!("../../22/input.txt"), params: &[], solution: day22 },
+    Day { number: 23, input: include_str!("../../23/input.txt"), params: &["a"], solution: day23 },
+];
+
+fn day1(_: &Args) -> Result<Box<dyn Puzzle>, String> {
+    Ok(Box::new(puzzle1::Day))
+}
+
+fn day12(args: &Args) -> Result<Box<dyn Puzzle>, String> {
+    Ok(Box::new(puzzle12::Day { c: args.try_param("c")? }))
+}
+
+fn day20(_: &Args) -> Result<Box<dyn Puzzle>, String> {
+    Ok(Box::new(puzzle20::Day))
+}
+
+fn day22(_: &Args) -> Result<Box<dyn Puzzle>, String> {
+    Ok(Box::new(puzzle22::Day))
+}
+
+fn day23(args: &Args) -> Result<Box<dyn Puzzle>, String> {
+    Ok(Box::new(puzzle23::Day { a: args.try_param("a")? }))
+}
+
+/// Runs the selected parts of `day`, returns `false` if one failed.
+fn run(day: &Day, args: &Args) -> bool {
+    let input = match args.read_input(day.input) {
+        Ok(input) => input,
+        Err(e) => {
+            eprintln!("day {}: could not read {}: {}", day.number, args.input_path().unwrap_or("input"), e);
+            return false;
+        }
+    };
+    let puzzle = match (day.solution)(args) {
+        Ok(puzzle) => puzzle,
+        Err(e) => {
+            eprintln!("day {}: {}", day.number, e);
+            return false;
+        }
+    };
+    let mut success = true;
+    for part in (1..puzzle.parts() + 1).filter(|&part| args.runs(part)) {
+        let start = Instant::now();
+        let result = puzzle.solve(&input, part);
+        let elapsed = start.elapsed();
+        match result {
+            Ok(answer) => println!("day {} part {}: {} ({:.2?})", day.number, part, answer, elapsed),
+            Err(e) => {
+                eprintln!("day {} part {}: {}", day.number, part, e);
+                success = false;
+            }
+        }
+    }
+    success
+}
+
+/// The expected answers of `day`, `None` if it has no answer file.
+fn expected_answers(day: &Day) -> io::Result<Option<Vec<String>>> {
+    let path = format!("{}/../{}/answers.txt", env!("CARGO_MANIFEST_DIR"), day.number);
+    match fs::read_to_string(path) {
+        Ok(answers) => Ok(Some(answers.lines().map(|line| line.trim().to_string()).collect())),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Counts of the rows in the table printed by `verify`.
+#[derive(Default)]
+struct Summary {
+    passed: u32,
+    failed: u32,
+    skipped: u32,
+}
+
+fn row(day: &Day, part: &str, expected: &str, answer: &str, result: &str) {
+    println!("{:>3}  {:>4}  {:<12}  {:<12}  {}", day.number, part, expected, answer, result);
+}
+
+/// Checks the selected parts of `day` against its answer file, printing a row per part.
+fn verify(day: &Day, args: &Args, summary: &mut Summary) {
+    let expected = match expected_answers(day) {
+        Ok(Some(expected)) => expected,
+        Ok(None) => {
+            row(day, "-", "-", "-", "skip (no answers.txt)");
+            summary.skipped += 1;
+            return;
+        }
+        Err(e) => {
+            row(day, "-", "-", "-", &format!("FAIL (could not read answers.txt: {})", e));
+            summary.failed += 1;
+            return;
+        }
+    };
+    let puzzle = (day.solution)(args).expect("the defaults are valid");
+    for part in (1..puzzle.parts() + 1).filter(|&part| args.runs(part)) {
+        let expected = match expected.get(part as usize - 1).filter(|answer| !answer.is_empty()) {
+            Some(expected) => expected,
+            None => {
+                row(day, &part.to_string(), "-", "-", "skip (no answer)");
+                summary.skipped += 1;
+                continue;
+            }
+        };
+        let start = Instant::now();
+        let result = puzzle.solve(day.input, part);
+        let elapsed = start.elapsed();
+        match result {
+            Ok(ref answer) if answer == expected => {
+                row(day, &part.to_string(), expected, answer, &format!("pass ({:.2?})", elapsed));
+                summary.passed += 1;
+            }
+            Ok(answer) => {
+                row(day, &part.to_string(), expected, &answer, "FAIL");
+                summary.failed += 1;
+            }
+            Err(e) => {
+                row(day, &part.to_string(), expected, "-", &format!("FAIL ({})", e));
+                summary.failed += 1;
+            }
+        }
+    }
+}
+
+fn usage() -> ! {
+    let days: Vec<_> = DAYS.iter().map(|d| d.number.to_string()).collect();
+    eprintln!("usage: aoc run <day>|all [--part 1|2] [options] [input|-]");
+    eprintln!("       aoc verify <day>|all [--part 1|2]");
+    eprintln!("days: {}", days.join(", "));
+    process::exit(2);
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let command = args.next().unwrap_or_else(|| usage());
+    if command != "run" && command != "verify" {
+        usage();
+    }
+    let selection = args.next().unwrap_or_else(|| usage());
+    let days: Vec<&Day> = if selection == "all" {
+        DAYS.iter().collect()
+    } else {
+        match DAYS.iter().find(|d| d.number.to_string() == selection) {
+            Some(day) => vec![day],
+            None => {
+                eprintln!("Unknown day {}", selection);
+                usage();
+            }
+        }
+    };
+    // the answer files hold the answers for the default options
+    let params: Vec<&str> = if command == "run" {
+        days.iter().flat_map(|d| d.params.iter().cloned()).collect()
+    } else {
+        Vec::new()
+    };
+    let args = Args::parse(args, &params).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        usage();
+    });
+    if command == "verify" && args.input_path().is_some() {
+        eprintln!("Only the bundled inputs can be verified");
+        usage();
+    }
+    if days.len() > 1 && args.input_path().is_some() {
+        eprintln!("An input can only be given for a single day");
+        usage();
+    }
+    if let (Some(part), 1) = (args.part(), days.len()) {
+        let parts = (days[0].solution)(&args).map_or(2, |puzzle| puzzle.parts());
+        if part > parts {
+            eprintln!("Day {} has no part {}", days[0].number, part);
+            process::exit(1);
+        }
+    }
+    if command == "verify" {
+        println!("{:>3}  {:>4}  {:<12}  {:<12}  result", "day", "part", "expected", "answer");
+        let mut summary = Summary::default();
+        for day in days {
+            verify(day, &args, &mut summary);
+        }
+        println!("{} passed, {} failed, {} skipped", summary.passed, summary.failed, summary.skipped);
+        if summary.failed > 0 {
+            process::exit(1);
+        }
+        return;
+    }
+    let start = Instant::now();
+    let mut success = true;
+    for day in days {
+        success &= run(day, &args);
+    }
+    if selection == "all" {
+        println!("total: {:.2?}", start.elapsed());
+    }
+    if !success {
+        process::exit(1);
+    }
+}
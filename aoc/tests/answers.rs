@@ -0,0 +1,18 @@
+//! Checks the answers to the bundled inputs against the `answers.txt` files.
+
+use std::process::Command;
+
+#[test]
+fn answers() {
+    let output = Command::new(env!("CARGO_BIN_EXE_aoc")).args(["verify", "all"]).output().unwrap();
+    let table = String::from_utf8_lossy(&output.stdout);
+    println!("{}", table);
+    assert!(output.status.success(), "{}{}", table, String::from_utf8_lossy(&output.stderr));
+    assert!(!table.contains("FAIL"));
+}
+
+#[test]
+fn rejects_options() {
+    let output = Command::new(env!("CARGO_BIN_EXE_aoc")).args(["verify", "23", "--a", "7"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
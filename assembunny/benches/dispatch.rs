@@ -0,0 +1,110 @@
+//! The step loop of `Machine` on puzzle 23 part one, with and without the
+//! optimizer, against the original loop which cloned and matched every
+//! instruction.
+//!
+//! See `1/benches/puzzle1.rs` for comparing two commits.
+
+#[macro_use]
+extern crate criterion;
+extern crate assembunny;
+
+use criterion::Criterion;
+
+use assembunny::{FromLocation, Instruction, InstructionSet, Machine, Register};
+
+/// The interpreter before instructions were decoded, kept as the baseline.
+struct Baseline {
+    reg_a: i32,
+    reg_b: i32,
+    reg_c: i32,
+    reg_d: i32,
+    pc: usize,
+    code: Vec<Instruction>,
+}
+
+impl Baseline {
+    fn run(&mut self) {
+        while let Some(()) = self.execute() {
+        }
+    }
+
+    fn execute(&mut self) -> Option<()> {
+        let inst = self.code[self.pc].clone();
+        match inst {
+            Instruction::Dec(reg) => *self.get_reg_mut(reg) -= 1,
+            Instruction::Inc(reg) => *self.get_reg_mut(reg) += 1,
+            Instruction::Cpy(ref src, dst) => *self.get_reg_mut(dst) = self.get_value(src),
+            Instruction::Jnz(ref value, ref offset) if self.get_value(value) != 0 => {
+                let offset = self.get_value(offset);
+                // pc will get autoincremented afterwards
+                self.pc = (self.pc as i32 + offset - 1) as usize;
+            }
+            Instruction::Tgl(ref offset) => {
+                let position = self.pc as i32 + self.get_value(offset);
+                if position >= 0 && (position as usize) < self.code.len() {
+                    let position = position as usize;
+                    self.code[position] = self.code[position].clone().toggle();
+                }
+            }
+            _ => (),
+        }
+        self.pc += 1;
+        if self.pc >= self.code.len() {
+            None
+        } else {
+            Some(())
+        }
+    }
+
+    fn get_value(&self, location: &FromLocation) -> i32 {
+        match *location {
+            FromLocation::Int(value) => value,
+            FromLocation::Reg(reg) => self.get_reg(reg),
+        }
+    }
+
+    fn get_reg(&self, reg: Register) -> i32 {
+        match reg {
+            Register::A => self.reg_a,
+            Register::B => self.reg_b,
+            Register::C => self.reg_c,
+            Register::D => self.reg_d,
+        }
+    }
+
+    fn get_reg_mut(&mut self, reg: Register) -> &mut i32 {
+        match reg {
+            Register::A => &mut self.reg_a,
+            Register::B => &mut self.reg_b,
+            Register::C => &mut self.reg_c,
+            Register::D => &mut self.reg_d,
+        }
+    }
+}
+
+fn dispatch(c: &mut Criterion) {
+    let code = InstructionSet::full().assemble(include_str!("../../23/input.txt")).unwrap();
+    let mut group = c.benchmark_group("puzzle23 part one");
+    group.bench_function("baseline", |b| b.iter(|| {
+        let mut machine = Baseline { reg_a: 7, reg_b: 0, reg_c: 0, reg_d: 0, pc: 0, code: code.clone() };
+        machine.run();
+        machine.reg_a
+    }));
+    group.bench_function("Machine", |b| b.iter(|| {
+        let mut machine = Machine::new(code.clone());
+        *machine.get_reg_mut(Register::A) = 7;
+        machine.run();
+        machine.get_reg(Register::A)
+    }));
+    group.bench_function("optimized", |b| b.iter(|| {
+        let mut machine = Machine::new(code.clone());
+        *machine.get_reg_mut(Register::A) = 7;
+        machine.enable_optimizer();
+        machine.run();
+        machine.get_reg(Register::A)
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, dispatch);
+criterion_main!(benches);
@@ -0,0 +1,28 @@
+extern crate assembunny;
+
+use std::env;
+use std::process;
+
+use assembunny::cfg::Cfg;
+
+fn main() {
+    let path = env::args().nth(1).unwrap_or_else(|| {
+        eprintln!("usage: cfg <program>");
+        process::exit(2);
+    });
+    let code = assembunny::load_program(&path).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+
+    let cfg = Cfg::build(&code);
+    for l in cfg.loops() {
+        let induction: Vec<_> = l.induction.iter().map(|reg| reg.to_string()).collect();
+        eprintln!("loop at {}: {} blocks, induction registers [{}]",
+                  cfg.blocks[l.header].start, l.blocks.len(), induction.join(", "));
+    }
+    for site in cfg.tgl_sites() {
+        eprintln!("tgl at {} modifies {}: {}", site.position, site.target, code[site.target]);
+    }
+    print!("{}", cfg.to_dot());
+}
@@ -0,0 +1,26 @@
+extern crate assembunny;
+
+use std::env;
+use std::process;
+
+use assembunny::clock;
+
+const LENGTH: usize = 100;
+
+fn main() {
+    let path = env::args().nth(1).unwrap_or_else(|| {
+        eprintln!("usage: clock <program>");
+        process::exit(2);
+    });
+    let code = assembunny::load_program(&path).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    match clock::find_clock_input(&code, 0..i32::MAX, LENGTH) {
+        Some(a) => println!("{}", a),
+        None => {
+            eprintln!("No value of a produces a clock signal");
+            process::exit(1);
+        }
+    }
+}
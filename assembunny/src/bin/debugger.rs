@@ -0,0 +1,39 @@
+extern crate assembunny;
+
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::process;
+
+use assembunny::debugger::{Command, Debugger};
+use assembunny::Machine;
+
+fn main() {
+    let path = env::args().nth(1).unwrap_or_else(|| {
+        eprintln!("usage: debugger <program>");
+        process::exit(2);
+    });
+    let code = assembunny::load_program(&path).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+
+    let mut debugger = Debugger::new(Machine::new(code));
+    println!("{}", debugger.execute(&Command::List(0)));
+    let stdin = io::stdin();
+    loop {
+        print!("(abd) ");
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        match line.parse() {
+            Ok(Command::Quit) => break,
+            Ok(command) => println!("{}", debugger.execute(&command)),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
@@ -0,0 +1,18 @@
+extern crate assembunny;
+
+use std::env;
+use std::process;
+
+use assembunny::disasm;
+
+fn main() {
+    let path = env::args().nth(1).unwrap_or_else(|| {
+        eprintln!("usage: disasm <program>");
+        process::exit(2);
+    });
+    let code = assembunny::load_program(&path).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    print!("{}", disasm::annotate(&code));
+}
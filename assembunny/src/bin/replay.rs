@@ -0,0 +1,49 @@
+extern crate assembunny;
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::process;
+
+use assembunny::{trace, Machine};
+
+const USAGE: &str = "usage: replay <trace> <program>";
+
+fn read(path: &str) -> String {
+    let mut input = String::new();
+    File::open(path).and_then(|mut f| f.read_to_string(&mut input)).unwrap_or_else(|e| {
+        eprintln!("Could not read {}: {}", path, e);
+        process::exit(1);
+    });
+    input
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() != 2 {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+
+    let trace = read(&args[0]);
+    let code = assembunny::load_program(&args[1]).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    // the trace header sets the registers and the optimizer mode
+    let mut machine = Machine::new(code);
+
+    match trace::replay(&mut machine, trace.lines()) {
+        Ok(None) => println!("trace and program agree"),
+        Ok(Some(divergence)) => {
+            println!("diverged at step {}", divergence.step);
+            println!("trace:   {}", divergence.expected.unwrap_or_else(|| "<end of trace>".into()));
+            println!("program: {}", divergence.actual.unwrap_or_else(|| "<halted>".into()));
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("{}: {}", args[0], e);
+            process::exit(1);
+        }
+    }
+}
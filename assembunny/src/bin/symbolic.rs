@@ -0,0 +1,42 @@
+extern crate assembunny;
+
+use std::env;
+use std::process;
+
+use assembunny::symbolic::SymbolicMachine;
+use assembunny::Register;
+
+const USAGE: &str = "usage: symbolic <program> [a [b [c [d]]]]";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() || args.len() > 5 {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+
+    let code = assembunny::load_program(&args[0]).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+
+    // every register is an unknown, the given values pick the path
+    let mut machine = SymbolicMachine::new(code);
+    for (k, &reg) in Register::ALL.iter().enumerate() {
+        let value = args.get(k + 1).map_or(Ok(0), |value| value.parse()).unwrap_or_else(|_| {
+            eprintln!("Invalid register value {}\n{}", args[k + 1], USAGE);
+            process::exit(2);
+        });
+        machine = machine.symbolic(reg, value);
+    }
+    if !machine.run(100_000_000) {
+        eprintln!("Program did not finish");
+        process::exit(1);
+    }
+    for &reg in Register::ALL.iter() {
+        println!("{} = {}", reg, machine.get_reg(reg));
+    }
+    for assumption in machine.assumptions() {
+        println!("assuming {}", assumption);
+    }
+}
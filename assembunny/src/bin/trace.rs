@@ -0,0 +1,49 @@
+extern crate assembunny;
+
+use std::env;
+use std::io::{self, Write};
+use std::process;
+
+use assembunny::{trace, Machine, Register};
+
+const USAGE: &str = "usage: trace [--optimize] <program> [a [b [c [d]]]]";
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let optimize = args.first().is_some_and(|arg| arg == "--optimize");
+    if optimize {
+        args.remove(0);
+    }
+    if args.is_empty() || args.len() > 5 {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+
+    let code = assembunny::load_program(&args[0]).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+
+    let mut machine = Machine::new(code);
+    for (&reg, value) in Register::ALL.iter().zip(&args[1..]) {
+        *machine.get_reg_mut(reg) = value.parse().unwrap_or_else(|_| {
+            eprintln!("Invalid register value {}\n{}", value, USAGE);
+            process::exit(2);
+        });
+    }
+    if optimize {
+        machine.enable_optimizer();
+    }
+    machine.enable_trace();
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    writeln!(out, "{}", trace::header(&machine)).unwrap();
+    let mut running = machine.halt_reason().is_none();
+    while running {
+        running = machine.execute().is_some();
+        for step in machine.take_trace() {
+            writeln!(out, "{}", step).unwrap();
+        }
+    }
+}
@@ -0,0 +1,470 @@
+//! Control-flow graph and loop analysis of a program.
+//!
+//! The analysis looks at the code as it is. Since `tgl` can change the code
+//! while it runs, every `tgl` whose target is known is reported as a
+//! self-modifying site, which may invalidate the graph once it executes.
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use instruction::{FromLocation, Instruction, Register};
+
+/// The instructions `start..end`, only the last one may jump.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    Block(usize),
+    /// The end of the program or a jump out of it, both halt the machine.
+    Exit,
+    /// A jump by a register or by an extension, which can go anywhere.
+    Unknown,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+    Always,
+    /// Taken if the register is not zero.
+    Taken(Register),
+    /// Taken if the register is zero.
+    NotTaken(Register),
+    /// Taken or not as an extension decides.
+    Extension,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Edge {
+    pub from: usize,
+    pub to: Target,
+    pub kind: EdgeKind,
+}
+
+/// A natural loop, given by the blocks of its body.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Loop {
+    pub header: usize,
+    pub blocks: BTreeSet<usize>,
+    /// Registers deciding whether the loop continues which the body only
+    /// changes with inc and dec.
+    pub induction: Vec<Register>,
+}
+
+/// A `tgl` whose target is known without running the program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TglSite {
+    pub position: usize,
+    pub target: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Cfg<'a> {
+    code: &'a [Instruction],
+    pub blocks: Vec<Block>,
+    pub edges: Vec<Edge>,
+}
+
+/// Where a `jnz` at `position` jumps to, if its offset is a constant.
+fn jump_target(code: &[Instruction], position: usize, offset: &FromLocation) -> Target {
+    match *offset {
+        FromLocation::Int(offset) => {
+            let target = position as i64 + i64::from(offset);
+            if target >= 0 && target < code.len() as i64 {
+                Target::Block(target as usize)
+            } else {
+                Target::Exit
+            }
+        }
+        FromLocation::Reg(_) => Target::Unknown,
+    }
+}
+
+impl<'a> Cfg<'a> {
+    pub fn build(code: &'a [Instruction]) -> Cfg<'a> {
+        let mut leaders = BTreeSet::new();
+        leaders.insert(0);
+        for (position, instruction) in code.iter().enumerate() {
+            match *instruction {
+                Instruction::Jnz(_, ref offset) => {
+                    leaders.insert(position + 1);
+                    if let Target::Block(target) = jump_target(code, position, offset) {
+                        leaders.insert(target);
+                    }
+                }
+                ref instruction if instruction.opcode().jumps() => {
+                    leaders.insert(position + 1);
+                }
+                _ => (),
+            }
+        }
+        let leaders: Vec<usize> = leaders.into_iter().filter(|&l| l < code.len()).collect();
+        let blocks: Vec<Block> = leaders.iter().enumerate()
+            .map(|(k, &start)| Block { start, end: leaders.get(k + 1).cloned().unwrap_or(code.len()) })
+            .collect();
+
+        let mut cfg = Cfg { code, blocks, edges: Vec::new() };
+        for from in 0..cfg.blocks.len() {
+            let last = cfg.blocks[from].end - 1;
+            let next = cfg.block_at(last + 1).map_or(Target::Exit, Target::Block);
+            let (jump, fallthrough) = match code[last] {
+                Instruction::Jnz(FromLocation::Int(0), _) => (None, Some(EdgeKind::Always)),
+                Instruction::Jnz(FromLocation::Int(_), ref offset) => {
+                    (Some((jump_target(code, last, offset), EdgeKind::Always)), None)
+                }
+                Instruction::Jnz(FromLocation::Reg(reg), ref offset) => {
+                    (Some((jump_target(code, last, offset), EdgeKind::Taken(reg))), Some(EdgeKind::NotTaken(reg)))
+                }
+                // an extension's offset isn't known, so the jump goes to `Unknown`
+                ref instruction if instruction.opcode().jumps() => {
+                    (Some((Target::Unknown, EdgeKind::Extension)), Some(EdgeKind::Extension))
+                }
+                _ => (None, Some(EdgeKind::Always)),
+            };
+            if let Some((to, kind)) = jump {
+                let to = match to {
+                    Target::Block(target) => cfg.block_at(target).map_or(Target::Exit, Target::Block),
+                    target => target,
+                };
+                cfg.edges.push(Edge { from, to, kind });
+            }
+            if let Some(kind) = fallthrough {
+                cfg.edges.push(Edge { from, to: next, kind });
+            }
+        }
+        cfg
+    }
+
+    /// The block starting at `position`.
+    fn block_at(&self, position: usize) -> Option<usize> {
+        self.blocks.binary_search_by_key(&position, |b| b.start).ok()
+    }
+
+    /// The block containing the instruction at `position`.
+    pub fn block_of(&self, position: usize) -> Option<usize> {
+        if position >= self.code.len() {
+            return None;
+        }
+        match self.blocks.binary_search_by_key(&position, |b| b.start) {
+            Ok(block) => Some(block),
+            Err(next) => Some(next - 1),
+        }
+    }
+
+    pub fn successors(&self, block: usize) -> Vec<usize> {
+        self.edges.iter()
+            .filter(|e| e.from == block)
+            .filter_map(|e| match e.to {
+                Target::Block(to) => Some(to),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn predecessors(&self, block: usize) -> Vec<usize> {
+        self.edges.iter().filter(|e| e.to == Target::Block(block)).map(|e| e.from).collect()
+    }
+
+    /// Blocks which can't be reached from the first block without jumping by a register.
+    ///
+    /// Each of them is treated as an additional entry, the blocks are visited in order.
+    fn roots(&self) -> Vec<usize> {
+        let mut roots = Vec::new();
+        let mut reached = vec![false; self.blocks.len()];
+        for root in 0..self.blocks.len() {
+            if reached[root] {
+                continue;
+            }
+            roots.push(root);
+            let mut work = vec![root];
+            while let Some(block) = work.pop() {
+                if !reached[block] {
+                    reached[block] = true;
+                    work.extend(self.successors(block));
+                }
+            }
+        }
+        roots
+    }
+
+    /// The blocks dominating each block.
+    ///
+    /// Jumps by a register are ignored, blocks only reachable through them
+    /// count as entries and are dominated by themselves only.
+    pub fn dominators(&self) -> Vec<BTreeSet<usize>> {
+        let roots = self.roots();
+        let mut dominators: Vec<Option<BTreeSet<usize>>> = vec![None; self.blocks.len()];
+        for &root in &roots {
+            dominators[root] = Some(Some(root).into_iter().collect());
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in (0..self.blocks.len()).filter(|b| !roots.contains(b)) {
+                let mut dominating: Option<BTreeSet<usize>> = None;
+                for pred in self.predecessors(block) {
+                    if let Some(ref pred_dominators) = dominators[pred] {
+                        dominating = Some(match dominating {
+                            Some(d) => d.intersection(pred_dominators).cloned().collect(),
+                            None => pred_dominators.clone(),
+                        });
+                    }
+                }
+                if let Some(mut dominating) = dominating {
+                    dominating.insert(block);
+                    if dominators[block].as_ref() != Some(&dominating) {
+                        dominators[block] = Some(dominating);
+                        changed = true;
+                    }
+                }
+            }
+        }
+        dominators.into_iter().map(|d| d.expect("every block is reachable from a root")).collect()
+    }
+
+    /// The natural loops, one per header, ordered by header.
+    pub fn loops(&self) -> Vec<Loop> {
+        let dominators = self.dominators();
+        let mut loops: Vec<Loop> = Vec::new();
+        for edge in &self.edges {
+            let header = match edge.to {
+                Target::Block(header) => header,
+                _ => continue,
+            };
+            if !dominators[edge.from].contains(&header) {
+                continue;
+            }
+            let mut blocks = BTreeSet::new();
+            blocks.insert(header);
+            let mut work = vec![edge.from];
+            while let Some(block) = work.pop() {
+                if blocks.insert(block) {
+                    work.extend(self.predecessors(block));
+                }
+            }
+            match loops.iter_mut().find(|l| l.header == header) {
+                Some(existing) => existing.blocks.extend(blocks),
+                None => loops.push(Loop { header, blocks, induction: Vec::new() }),
+            }
+        }
+        for l in &mut loops {
+            l.induction = self.induction_registers(&l.blocks);
+        }
+        loops.sort_by_key(|l| l.header);
+        loops
+    }
+
+    fn induction_registers(&self, body: &BTreeSet<usize>) -> Vec<Register> {
+        let code: Vec<&Instruction> = body.iter()
+            .flat_map(|&b| &self.code[self.blocks[b].start..self.blocks[b].end])
+            .collect();
+        let mut induction = Vec::new();
+        for edge in self.edges.iter().filter(|e| body.contains(&e.from)) {
+            let reg = match edge.kind {
+                EdgeKind::Taken(reg) | EdgeKind::NotTaken(reg) => reg,
+                EdgeKind::Always | EdgeKind::Extension => continue,
+            };
+            let counted = code.iter().any(|i| **i == Instruction::Inc(reg) || **i == Instruction::Dec(reg));
+            let copied = code.iter().any(|i| match **i {
+                Instruction::Cpy(_, dst) => dst == reg,
+                _ => false,
+            });
+            if counted && !copied && !induction.contains(&reg) {
+                induction.push(reg);
+            }
+        }
+        induction
+    }
+
+    /// The offset of the `jnz` or `tgl` at every position, if it is known statically.
+    ///
+    /// An offset by a register counts if the register is set to a constant
+    /// earlier in the same block. The edges of the graph don't use these, as
+    /// a jump by a register may enter a block after the constant was set.
+    pub fn known_offsets(&self) -> Vec<Option<i32>> {
+        let mut offsets = vec![None; self.code.len()];
+        for block in &self.blocks {
+            let mut known: [Option<i32>; 4] = [None; 4];
+            for (position, instruction) in self.code.iter().enumerate().take(block.end).skip(block.start) {
+                match *instruction {
+                    Instruction::Cpy(ref from, dst) => {
+                        known[dst as usize] = match *from {
+                            FromLocation::Int(value) => Some(value),
+                            FromLocation::Reg(src) => known[src as usize],
+                        };
+                    }
+                    Instruction::Inc(reg) => known[reg as usize] = known[reg as usize].map(|v| v.wrapping_add(1)),
+                    Instruction::Dec(reg) => known[reg as usize] = known[reg as usize].map(|v| v.wrapping_sub(1)),
+                    Instruction::Jnz(_, ref offset) | Instruction::Tgl(ref offset) => {
+                        offsets[position] = match *offset {
+                            FromLocation::Int(value) => Some(value),
+                            FromLocation::Reg(reg) => known[reg as usize],
+                        };
+                    }
+                    Instruction::Out(_) | Instruction::Invalid(_) => (),
+                    // an extension may change any register
+                    Instruction::Extension(_) => known = [None; 4],
+                }
+            }
+        }
+        offsets
+    }
+
+    /// Every `tgl` whose target lies in the program and is known statically.
+    ///
+    /// A `tgl` by a register counts if the register is set to a constant
+    /// earlier in the same block, see `known_offsets`.
+    pub fn tgl_sites(&self) -> Vec<TglSite> {
+        let offsets = self.known_offsets();
+        let mut sites = Vec::new();
+        for (position, instruction) in self.code.iter().enumerate() {
+            if let Instruction::Tgl(_) = *instruction {
+                let target = offsets[position].map(|offset| position as i64 + i64::from(offset));
+                if let Some(target) = target.filter(|&t| t >= 0 && t < self.code.len() as i64) {
+                    sites.push(TglSite { position, target: target as usize });
+                }
+            }
+        }
+        sites
+    }
+
+    /// The graph in Graphviz DOT format.
+    ///
+    /// Self-modifying `tgl` sites get a dotted red edge to the block they change.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "digraph cfg {{").unwrap();
+        writeln!(out, "    node [shape=box fontname=monospace];").unwrap();
+        for (k, block) in self.blocks.iter().enumerate() {
+            let mut label = String::new();
+            for position in block.start..block.end {
+                write!(label, "{}: {}\\l", position, self.code[position]).unwrap();
+            }
+            writeln!(out, "    b{} [label=\"{}\"];", k, label).unwrap();
+        }
+        if self.edges.iter().any(|e| e.to == Target::Exit) {
+            writeln!(out, "    exit [shape=oval];").unwrap();
+        }
+        if self.edges.iter().any(|e| e.to == Target::Unknown) {
+            writeln!(out, "    unknown [shape=diamond label=\"?\"];").unwrap();
+        }
+        for edge in &self.edges {
+            let to = match edge.to {
+                Target::Block(to) => format!("b{}", to),
+                Target::Exit => "exit".into(),
+                Target::Unknown => "unknown".into(),
+            };
+            let label = match edge.kind {
+                EdgeKind::Always => String::new(),
+                EdgeKind::Taken(reg) => format!(" [label=\"{} != 0\"]", reg),
+                EdgeKind::NotTaken(reg) => format!(" [label=\"{} == 0\"]", reg),
+                EdgeKind::Extension => " [style=dashed]".into(),
+            };
+            writeln!(out, "    b{} -> {}{};", edge.from, to, label).unwrap();
+        }
+        for site in self.tgl_sites() {
+            if let (Some(from), Some(to)) = (self.block_of(site.position), self.block_of(site.target)) {
+                writeln!(out, "    b{} -> b{} [style=dotted color=red label=\"tgl {}\"];",
+                         from, to, site.target).unwrap();
+            }
+        }
+        writeln!(out, "}}").unwrap();
+        out
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parse::parse_program;
+
+    fn blocks(cfg: &Cfg) -> Vec<(usize, usize)> {
+        cfg.blocks.iter().map(|b| (b.start, b.end)).collect()
+    }
+
+    #[test]
+    fn basic_blocks() {
+        let code = parse_program("cpy 41 a\ninc a\ninc a\ndec a\njnz a 2\ndec a").unwrap();
+        let cfg = Cfg::build(&code);
+        assert_eq!(blocks(&cfg), vec![(0, 5), (5, 6)]);
+        assert_eq!(cfg.edges, vec![
+            Edge { from: 0, to: Target::Exit, kind: EdgeKind::Taken(Register::A) },
+            Edge { from: 0, to: Target::Block(1), kind: EdgeKind::NotTaken(Register::A) },
+            Edge { from: 1, to: Target::Exit, kind: EdgeKind::Always },
+        ]);
+        assert_eq!(cfg.dominators()[1], vec![0, 1].into_iter().collect());
+    }
+
+    #[test]
+    fn constant_conditions() {
+        let code = parse_program("jnz 1 2\ninc a\njnz 0 -2\njnz b c").unwrap();
+        let cfg = Cfg::build(&code);
+        assert_eq!(blocks(&cfg), vec![(0, 1), (1, 2), (2, 3), (3, 4)]);
+        assert_eq!(cfg.edges, vec![
+            Edge { from: 0, to: Target::Block(2), kind: EdgeKind::Always },
+            Edge { from: 1, to: Target::Block(2), kind: EdgeKind::Always },
+            Edge { from: 2, to: Target::Block(3), kind: EdgeKind::Always },
+            Edge { from: 3, to: Target::Unknown, kind: EdgeKind::Taken(Register::B) },
+            Edge { from: 3, to: Target::Exit, kind: EdgeKind::NotTaken(Register::B) },
+        ]);
+    }
+
+    #[test]
+    fn register_jumps() {
+        let code = parse_program("inc a\ncpy -2 c\njnz a c\njnz 1 c").unwrap();
+        let cfg = Cfg::build(&code);
+        assert_eq!(blocks(&cfg), vec![(0, 3), (3, 4)]);
+        assert_eq!(cfg.edges, vec![
+            Edge { from: 0, to: Target::Unknown, kind: EdgeKind::Taken(Register::A) },
+            Edge { from: 0, to: Target::Block(1), kind: EdgeKind::NotTaken(Register::A) },
+            Edge { from: 1, to: Target::Unknown, kind: EdgeKind::Always },
+        ]);
+        assert_eq!(cfg.known_offsets(), vec![None, None, Some(-2), None]);
+    }
+
+    #[test]
+    fn nested_loops() {
+        let code = parse_program(include_str!("../../23/input.txt")).unwrap();
+        let cfg = Cfg::build(&code);
+        let loops: Vec<_> = cfg.loops().into_iter()
+            .map(|l| (cfg.blocks[l.header].start, l.blocks.len(), l.induction))
+            .collect();
+        assert_eq!(loops, vec![
+            (4, 3, vec![Register::D]),
+            (5, 1, vec![Register::C]),
+            (13, 1, vec![Register::D]),
+            // the outer loop at 20 is only entered through the jump by d
+            (21, 1, vec![Register::D]),
+        ]);
+    }
+
+    #[test]
+    fn tgl_sites() {
+        let code = parse_program("tgl 2\ncpy 3 c\ninc c\ntgl c\ninc a\njnz a 2\ntgl c\ntgl 10").unwrap();
+        let cfg = Cfg::build(&code);
+        assert_eq!(cfg.tgl_sites(), vec![
+            TglSite { position: 0, target: 2 },
+            TglSite { position: 3, target: 7 },
+        ]);
+    }
+
+    #[test]
+    fn dot() {
+        let code = parse_program("tgl 1\ninc a\ndec b\njnz b -2").unwrap();
+        assert_eq!(Cfg::build(&code).to_dot(), "\
+digraph cfg {
+    node [shape=box fontname=monospace];
+    b0 [label=\"0: tgl 1\\l\"];
+    b1 [label=\"1: inc a\\l2: dec b\\l3: jnz b -2\\l\"];
+    exit [shape=oval];
+    b0 -> b1;
+    b1 -> b1 [label=\"b != 0\"];
+    b1 -> exit [label=\"b == 0\"];
+    b0 -> b1 [style=dotted color=red label=\"tgl 1\"];
+}
+");
+    }
+}
@@ -0,0 +1,115 @@
+//! Search for inputs making a program output a clock signal.
+
+use std::ops::Range;
+
+use instruction::{Instruction, Register};
+use machine::Machine;
+use output::Sink;
+
+/// How long a candidate may run without completing the signal.
+pub const MAX_STEPS: u64 = 1_000_000;
+
+/// Checks the output against 0, 1, 0, 1, ... as it arrives.
+struct Signal {
+    seen: usize,
+    valid: bool,
+}
+
+impl Sink<i32> for Signal {
+    fn emit(&mut self, value: i32) {
+        self.valid &= value == (self.seen % 2) as i32;
+        self.seen += 1;
+    }
+}
+
+/// Whether the program outputs `0, 1, 0, 1, ...` for `length` values when register a starts at `a`.
+///
+/// The optimizer is used. Gives up after `MAX_STEPS` steps.
+pub fn is_clock_signal(code: &[Instruction], a: i32, length: usize) -> bool {
+    let mut machine = Machine::new(code.to_vec());
+    machine.enable_optimizer();
+    *machine.get_reg_mut(Register::A) = a;
+    let mut signal = Signal { seen: 0, valid: true };
+    for _ in 0..MAX_STEPS {
+        let running = machine.execute_with(&mut signal).is_some();
+        if !signal.valid {
+            return false;
+        }
+        if signal.seen >= length {
+            return true;
+        }
+        if !running {
+            return false;
+        }
+    }
+    false
+}
+
+/// The smallest value of register a among `candidates` producing a clock signal of `length` values.
+pub fn find_clock_input(code: &[Instruction], candidates: Range<i32>, length: usize) -> Option<i32> {
+    candidates.into_iter().find(|&a| is_clock_signal(code, a, length))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parse::parse_program;
+
+    // outputs the bits of a + 16, least significant first, forever
+    const CLOCK: &str = "\
+cpy a d
+cpy 4 c
+cpy 4 b
+inc d
+dec b
+jnz b -2
+dec c
+jnz c -5
+cpy d a
+jnz 0 0
+cpy a b
+cpy 0 a
+cpy 2 c
+jnz b 2
+jnz 1 6
+dec b
+dec c
+jnz c -4
+inc a
+jnz 1 -7
+cpy 2 b
+jnz c 2
+jnz 1 4
+dec b
+dec c
+jnz 1 -4
+jnz 0 0
+out b
+jnz a -19
+jnz 1 -21";
+
+    #[test]
+    fn bits() {
+        let mut machine = Machine::new(parse_program(CLOCK).unwrap());
+        *machine.get_reg_mut(Register::A) = 3;
+        // 19 = 0b10011
+        assert_eq!(machine.run_until_outputs(7), Ok(vec![1, 1, 0, 0, 1, 1, 1]));
+    }
+
+    #[test]
+    fn find_input() {
+        let code = parse_program(CLOCK).unwrap();
+        // 26 + 16 = 0b101010
+        assert_eq!(find_clock_input(&code, 0..100, 12), Some(26));
+        assert!(!is_clock_signal(&code, 27, 12));
+        assert_eq!(find_clock_input(&code, 0..26, 12), None);
+    }
+
+    #[test]
+    fn halting_program() {
+        let code = parse_program("out 0\nout 1").unwrap();
+        assert!(is_clock_signal(&code, 0, 2));
+        assert!(!is_clock_signal(&code, 0, 4));
+    }
+}
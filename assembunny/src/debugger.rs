@@ -0,0 +1,311 @@
+//! Step debugger driving a `Machine` with text commands.
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::str::FromStr;
+
+use disasm;
+use instruction::Register;
+use machine::Machine;
+
+/// How many steps `back` can undo.
+pub const HISTORY_DEPTH: usize = 10_000;
+
+pub const HELP: &str = "\
+step [N]         execute N instructions (default 1)
+back [N]         undo N instructions (default 1)
+continue         run until a breakpoint, a watch or the end of the program
+break <pc>       stop before executing the instruction at pc
+delete <pc>      remove a breakpoint
+watch <reg>      stop whenever the register changes
+unwatch <reg>    remove a watch
+set <reg> <val>  change a register
+regs             show the registers
+list [N]         show N instructions around pc (default 5)
+dump             show the whole program with labels for the jump targets
+help             show this help
+quit             leave the debugger";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Step(usize),
+    Back(usize),
+    Continue,
+    Break(usize),
+    Delete(usize),
+    Watch(Register),
+    Unwatch(Register),
+    Set(Register, i32),
+    Registers,
+    List(usize),
+    Dump,
+    Help,
+    Quit,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split_whitespace();
+        let command = match parts.next() {
+            Some("step") | Some("s") => Command::Step(optional_number(parts.next(), 1)?),
+            Some("back") => Command::Back(optional_number(parts.next(), 1)?),
+            Some("continue") | Some("c") => Command::Continue,
+            Some("break") | Some("b") => Command::Break(number(parts.next())?),
+            Some("delete") | Some("d") => Command::Delete(number(parts.next())?),
+            Some("watch") | Some("w") => Command::Watch(register(parts.next())?),
+            Some("unwatch") => Command::Unwatch(register(parts.next())?),
+            Some("set") => Command::Set(register(parts.next())?, number(parts.next())?),
+            Some("regs") | Some("r") => Command::Registers,
+            Some("list") | Some("l") => Command::List(optional_number(parts.next(), 5)?),
+            Some("dump") => Command::Dump,
+            Some("help") | Some("h") => Command::Help,
+            Some("quit") | Some("q") => Command::Quit,
+            Some(other) => return Err(format!("Unknown command {}, try help", other)),
+            None => return Err("No command given".into()),
+        };
+        match parts.next() {
+            Some(extra) => Err(format!("Unexpected argument {}", extra)),
+            None => Ok(command),
+        }
+    }
+}
+
+fn number<T: FromStr>(s: Option<&str>) -> Result<T, String> {
+    let s = s.ok_or("Missing argument")?;
+    s.parse().map_err(|_| format!("Invalid number {}", s))
+}
+
+fn optional_number(s: Option<&str>, default: usize) -> Result<usize, String> {
+    match s {
+        Some(_) => number(s),
+        None => Ok(default),
+    }
+}
+
+fn register(s: Option<&str>) -> Result<Register, String> {
+    let s = s.ok_or("Missing register")?;
+    s.parse().map_err(|e| format!("{}", e))
+}
+
+#[derive(Debug)]
+pub struct Debugger {
+    machine: Machine,
+    breakpoints: BTreeSet<usize>,
+    watches: Vec<Register>,
+}
+
+impl Debugger {
+    pub fn new(mut machine: Machine) -> Debugger {
+        machine.enable_history(HISTORY_DEPTH);
+        Debugger { machine, breakpoints: BTreeSet::new(), watches: Vec::new() }
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.machine.halt_reason().is_some()
+    }
+
+    fn at_breakpoint(&self) -> bool {
+        let pc = self.machine.pc();
+        pc >= 0 && self.breakpoints.contains(&(pc as usize))
+    }
+
+    /// Executes a command and returns the text to show to the user.
+    pub fn execute(&mut self, command: &Command) -> String {
+        match *command {
+            Command::Step(steps) => self.resume(Some(steps)),
+            Command::Back(steps) => {
+                let undone = (0..steps).take_while(|_| self.machine.step_back()).count();
+                if undone < steps {
+                    format!("undid {} steps, no more history\n{}", undone, self.list(1))
+                } else {
+                    self.list(1)
+                }
+            }
+            Command::Continue => self.resume(None),
+            Command::Break(pc) => {
+                self.breakpoints.insert(pc);
+                format!("breakpoint at {}", pc)
+            }
+            Command::Delete(pc) => {
+                if self.breakpoints.remove(&pc) {
+                    format!("deleted breakpoint at {}", pc)
+                } else {
+                    format!("no breakpoint at {}", pc)
+                }
+            }
+            Command::Watch(reg) => {
+                if !self.watches.contains(&reg) {
+                    self.watches.push(reg);
+                }
+                format!("watching {}", reg)
+            }
+            Command::Unwatch(reg) => {
+                self.watches.retain(|&r| r != reg);
+                format!("stopped watching {}", reg)
+            }
+            Command::Set(reg, value) => {
+                *self.machine.get_reg_mut(reg) = value;
+                self.registers()
+            }
+            Command::Registers => self.registers(),
+            Command::List(lines) => self.list(lines),
+            Command::Dump => {
+                let mut out = disasm::annotate(self.machine.code());
+                out.pop();
+                out
+            }
+            Command::Help => HELP.into(),
+            Command::Quit => String::new(),
+        }
+    }
+
+    /// Executes up to `steps` instructions or until the program stops.
+    fn resume(&mut self, steps: Option<usize>) -> String {
+        let mut executed = 0;
+        while steps.is_none_or(|steps| executed < steps) {
+            if self.is_halted() {
+                return format!("halted after {} steps", executed);
+            }
+            let before: Vec<i32> = self.watches.iter().map(|&reg| self.machine.get_reg(reg)).collect();
+            self.machine.execute();
+            executed += 1;
+
+            let mut out = String::new();
+            for (&reg, old) in self.watches.iter().zip(before) {
+                let new = self.machine.get_reg(reg);
+                if new != old {
+                    writeln!(out, "watch {}: {} -> {}", reg, old, new).unwrap();
+                }
+            }
+            if self.at_breakpoint() {
+                writeln!(out, "breakpoint at {}", self.machine.pc()).unwrap();
+            }
+            if !out.is_empty() {
+                return out + &self.list(1);
+            }
+        }
+        self.list(1)
+    }
+
+    fn registers(&self) -> String {
+        let mut out = String::new();
+        for &reg in Register::ALL.iter() {
+            write!(out, "{}={} ", reg, self.machine.get_reg(reg)).unwrap();
+        }
+        write!(out, "pc={}", self.machine.pc()).unwrap();
+        out
+    }
+
+    /// Lists the code around pc, `context` instructions before and after it.
+    fn list(&self, context: usize) -> String {
+        let code = self.machine.code();
+        let pc = self.machine.pc();
+        let center = pc.max(0).min(code.len() as i64) as usize;
+        let first = center.saturating_sub(context);
+        let last = (center + context + 1).min(code.len());
+        let mut out = String::new();
+        for (i, instruction) in code.iter().enumerate().take(last).skip(first) {
+            let marker = if i as i64 == pc { "=>" } else { "  " };
+            let breakpoint = if self.breakpoints.contains(&i) { "*" } else { " " };
+            writeln!(out, "{}{}{:4}  {}", marker, breakpoint, i, instruction).unwrap();
+        }
+        if let Some(halt) = self.machine.halt_reason() {
+            writeln!(out, "=> {:4}  <halted: {:?}>", pc, halt).unwrap();
+        }
+        out.pop();
+        out
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parse::parse_program;
+
+    fn debugger(input: &str) -> Debugger {
+        Debugger::new(Machine::new(parse_program(input).unwrap()))
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!("step".parse(), Ok(Command::Step(1)));
+        assert_eq!("s 10".parse(), Ok(Command::Step(10)));
+        assert_eq!("break 4".parse(), Ok(Command::Break(4)));
+        assert_eq!("watch c".parse(), Ok(Command::Watch(Register::C)));
+        assert_eq!("set a 12".parse(), Ok(Command::Set(Register::A, 12)));
+        assert_eq!("back 3".parse(), Ok(Command::Back(3)));
+        assert!("break".parse::<Command>().is_err());
+        assert!("step 1 2".parse::<Command>().is_err());
+        assert!("jump".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn dump_after_toggle() {
+        let mut debugger = debugger("tgl 2\ninc a\njnz a -1");
+        debugger.execute(&Command::Step(1));
+        assert_eq!(debugger.execute(&Command::Dump),
+                   "    tgl 2\n    inc a\n    invalid cpy a -1 ; toggled, skipped when executed");
+    }
+
+    #[test]
+    fn step_stops_at_breakpoint() {
+        let mut debugger = debugger("inc a\ninc a\ninc a\ninc a");
+        debugger.execute(&Command::Break(2));
+        let out = debugger.execute(&Command::Step(3));
+        assert!(out.starts_with("breakpoint at 2"));
+        assert_eq!(debugger.machine().pc(), 2);
+        debugger.execute(&Command::Continue);
+        assert!(debugger.is_halted());
+        assert_eq!(debugger.machine().get_reg(Register::A), 4);
+    }
+
+    #[test]
+    fn continue_stops_on_watch() {
+        let mut debugger = debugger("inc a\ninc b\ninc a");
+        assert_eq!(debugger.execute(&Command::Watch(Register::B)), "watching b");
+        let out = debugger.execute(&Command::Continue);
+        assert!(out.starts_with("watch b: 0 -> 1"));
+        assert_eq!(debugger.machine().pc(), 2);
+        assert_eq!(debugger.execute(&Command::Registers), "a=1 b=1 c=0 d=0 pc=2");
+    }
+
+    #[test]
+    fn step_back_and_forth() {
+        let mut debugger = debugger("tgl 2\ninc a\ninc a\ninc a");
+        debugger.execute(&Command::Step(3));
+        assert_eq!(debugger.machine().get_reg(Register::A), 0);
+        assert_eq!(debugger.execute(&Command::Back(2)), "      0  tgl 2\n=>    1  inc a\n      2  dec a");
+        assert_eq!(debugger.execute(&Command::Back(5)), "undid 1 steps, no more history\n=>    0  tgl 2\n      1  inc a");
+        assert_eq!(debugger.machine().code()[2].to_string(), "inc a");
+        debugger.execute(&Command::Continue);
+        assert_eq!(debugger.machine().get_reg(Register::A), 1);
+    }
+
+    #[test]
+    fn step_past_end() {
+        let mut debugger = debugger("inc a");
+        assert_eq!(debugger.execute(&Command::Step(5)), "halted after 1 steps");
+    }
+
+    #[test]
+    fn list_after_jump_out_of_range() {
+        let mut debugger = debugger("inc a\njnz 1 -5");
+        debugger.execute(&Command::Continue);
+        assert_eq!(debugger.execute(&Command::List(1)),
+                   "      0  inc a\n      1  jnz 1 -5\n=>   -4  <halted: JumpOutOfRange { from: 1, target: -4 }>");
+    }
+
+    #[test]
+    fn list_shows_toggled_code() {
+        let mut debugger = debugger("tgl 1\ninc a");
+        debugger.execute(&Command::Step(1));
+        assert_eq!(debugger.execute(&Command::List(1)), "      0  tgl 1\n=>    1  dec a");
+    }
+}
@@ -0,0 +1,124 @@
+//! Instructions decoded for `Machine`.
+//!
+//! Instructions are decoded once into a compact form holding register indices
+//! and inlined immediates, so a step neither clones an instruction nor matches
+//! on register names. `tgl` changes the source instruction and re-decodes it.
+//! Extension instructions aren't decoded, they are executed through their
+//! opcode. The fused ops of the optimizer are decoded the same way.
+
+use instruction::{FromLocation, Instruction};
+use optimizer::Op;
+use word::Word;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand<W> {
+    Imm(W),
+    Reg(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decoded<W> {
+    Inc(usize),
+    Dec(usize),
+    Cpy(Operand<W>, usize),
+    /// A `jnz` with a constant condition which isn't zero.
+    Jump(Operand<W>),
+    Jnz(usize, Operand<W>),
+    Tgl(Operand<W>),
+    Out(Operand<W>),
+    /// A `jnz` with a zero condition or an invalid instruction.
+    Nop,
+    Extension,
+}
+
+/// A fused op, see `optimizer::Op`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fused<W> {
+    Add { dst: usize, src: usize },
+    Mul { dst: usize, factor: Operand<W>, inner: usize, outer: usize },
+}
+
+impl<W> Fused<W> {
+    /// Number of instructions the op replaces.
+    pub fn slots(&self) -> usize {
+        match *self {
+            Fused::Add { .. } => 3,
+            Fused::Mul { .. } => 6,
+        }
+    }
+}
+
+fn operand<W: Word>(location: &FromLocation) -> Operand<W> {
+    match *location {
+        FromLocation::Int(value) => Operand::Imm(W::from_i32(value)),
+        FromLocation::Reg(reg) => Operand::Reg(reg as usize),
+    }
+}
+
+pub fn decode<W: Word>(instruction: &Instruction) -> Decoded<W> {
+    match *instruction {
+        Instruction::Inc(reg) => Decoded::Inc(reg as usize),
+        Instruction::Dec(reg) => Decoded::Dec(reg as usize),
+        Instruction::Cpy(ref src, dst) => Decoded::Cpy(operand(src), dst as usize),
+        Instruction::Jnz(FromLocation::Int(0), _) => Decoded::Nop,
+        Instruction::Jnz(FromLocation::Int(_), ref offset) => Decoded::Jump(operand(offset)),
+        Instruction::Jnz(FromLocation::Reg(reg), ref offset) => Decoded::Jnz(reg as usize, operand(offset)),
+        Instruction::Tgl(ref offset) => Decoded::Tgl(operand(offset)),
+        Instruction::Out(ref value) => Decoded::Out(operand(value)),
+        Instruction::Invalid(_) => Decoded::Nop,
+        Instruction::Extension(_) => Decoded::Extension,
+    }
+}
+
+pub fn decode_op<W: Word>(op: &Op) -> Fused<W> {
+    match *op {
+        Op::Add { dst, src } => Fused::Add { dst: dst as usize, src: src as usize },
+        Op::Mul { dst, ref factor, inner, outer } => Fused::Mul {
+            dst: dst as usize,
+            factor: operand(factor),
+            inner: inner as usize,
+            outer: outer as usize,
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use instruction::Register;
+    use optimizer::optimize;
+    use parse::parse_program;
+
+    #[test]
+    fn decode_instructions() {
+        let code = parse_program("cpy 5 a\njnz 0 c\njnz 1 -2\njnz b 2\ntgl d\ninvalid dec 3").unwrap();
+        let decoded: Vec<Decoded<i32>> = code.iter().map(decode).collect();
+        assert_eq!(decoded, vec![
+            Decoded::Cpy(Operand::Imm(5), 0),
+            Decoded::Nop,
+            Decoded::Jump(Operand::Imm(-2)),
+            Decoded::Jnz(1, Operand::Imm(2)),
+            Decoded::Tgl(Operand::Reg(3)),
+            Decoded::Nop,
+        ]);
+    }
+
+    #[test]
+    fn decode_ops() {
+        let code = parse_program("cpy b c\ninc a\ndec c\njnz c -2\ndec d\njnz d -5").unwrap();
+        let ops = optimize(&code);
+        assert_eq!(decode_op::<i32>(ops[0].as_ref().unwrap()), Fused::Mul {
+            dst: 0,
+            factor: Operand::Reg(1),
+            inner: 2,
+            outer: 3,
+        });
+        assert_eq!(decode_op::<i32>(ops[1].as_ref().unwrap()), Fused::Add { dst: 0, src: 2 });
+        for op in ops.iter().flatten() {
+            assert_eq!(decode_op::<i32>(op).slots(), op.slots());
+        }
+        let op = Op::Mul { dst: Register::A, factor: FromLocation::Int(7), inner: Register::B, outer: Register::C };
+        assert_eq!(decode_op::<i64>(&op), Fused::Mul { dst: 0, factor: Operand::Imm(7), inner: 1, outer: 2 });
+    }
+}
@@ -0,0 +1,120 @@
+//! Writes programs back out as assembunny source.
+//!
+//! `format` produces plain source which parses back to the same program.
+//! `annotate` is meant for reading: jump targets get labels and instructions
+//! which can't be executed are marked. Its output can still be assembled.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use instruction::{FromLocation, Instruction};
+
+/// One instruction per line, `parse_program(&format(code)) == Ok(code)`.
+pub fn format(code: &[Instruction]) -> String {
+    let mut out = String::new();
+    for instruction in code {
+        writeln!(out, "{}", instruction).unwrap();
+    }
+    out
+}
+
+/// The position a `jnz` with a constant offset jumps to, if it stays in the program.
+///
+/// A jump to just past the last instruction ends the program and counts as in range.
+fn jump_target(position: usize, instruction: &Instruction, len: usize) -> Option<usize> {
+    match *instruction {
+        Instruction::Jnz(_, FromLocation::Int(offset)) => {
+            let target = position as i64 + i64::from(offset);
+            if target >= 0 && target <= len as i64 {
+                Some(target as usize)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Source with labels for the jump targets, `assemble(&annotate(code)) == Ok(code)`.
+///
+/// Jumps with a constant offset are printed with the label of their target,
+/// jumps leaving the program and invalid instructions get a comment.
+pub fn annotate(code: &[Instruction]) -> String {
+    let targets: BTreeSet<usize> = code.iter().enumerate()
+        .filter_map(|(position, instruction)| jump_target(position, instruction, code.len()))
+        .collect();
+    let labels: BTreeMap<usize, String> = targets.iter().enumerate()
+        .map(|(k, &target)| (target, format!("L{}", k)))
+        .collect();
+
+    let mut out = String::new();
+    for (position, instruction) in code.iter().enumerate() {
+        if let Some(label) = labels.get(&position) {
+            writeln!(out, "{}:", label).unwrap();
+        }
+        match *instruction {
+            Instruction::Jnz(ref value, FromLocation::Int(_)) => {
+                match jump_target(position, instruction, code.len()) {
+                    Some(target) => writeln!(out, "    jnz {} {}", value, labels[&target]),
+                    None => writeln!(out, "    {} ; jumps out of the program", instruction),
+                }.unwrap();
+            }
+            Instruction::Invalid(_) => writeln!(out, "    {} ; toggled, skipped when executed", instruction).unwrap(),
+            _ => writeln!(out, "    {}", instruction).unwrap(),
+        }
+    }
+    if let Some(label) = labels.get(&code.len()) {
+        writeln!(out, "{}:", label).unwrap();
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use machine::Machine;
+    use parse::{assemble, parse_program};
+
+    #[test]
+    fn format_round_trip() {
+        let input = "cpy 41 a\ninc a\ninc a\ndec a\njnz a 2\ndec a\ntgl c\ninvalid cpy 1 2\ninvalid inc -3\n";
+        let code = parse_program(input).unwrap();
+        assert_eq!(format(&code), input);
+        assert_eq!(parse_program(&format(&code)), Ok(code));
+    }
+
+    #[test]
+    fn format_toggled_code() {
+        let mut machine = Machine::new(parse_program("tgl 2\ntgl 2\njnz 1 2\ninc a").unwrap());
+        machine.run();
+        let code = machine.code().to_vec();
+        assert_eq!(format(&code), "tgl 2\ntgl 2\ninvalid cpy 1 2\ndec a\n");
+        assert_eq!(parse_program(&format(&code)), Ok(code));
+    }
+
+    #[test]
+    fn annotate_labels() {
+        let code = parse_program("cpy 2 b\ninc a\ndec b\njnz b -2\njnz a 2\njnz 1 5\njnz c d\njnz 1 1").unwrap();
+        assert_eq!(annotate(&code), "    cpy 2 b
+L0:
+    inc a
+    dec b
+    jnz b L0
+    jnz a L1
+    jnz 1 5 ; jumps out of the program
+L1:
+    jnz c d
+    jnz 1 L2
+L2:
+");
+        assert_eq!(assemble(&annotate(&code)), Ok(code));
+    }
+
+    #[test]
+    fn annotate_invalid() {
+        let mut machine = Machine::new(parse_program("tgl 1\njnz 1 2").unwrap());
+        machine.run();
+        assert_eq!(annotate(machine.code()), "    tgl 1\n    invalid cpy 1 2 ; toggled, skipped when executed\n");
+    }
+}
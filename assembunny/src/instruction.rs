@@ -0,0 +1,426 @@
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use opcode::{self, Extension, Opcode};
+use parse::{self, ParseError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Register {
+    A,
+    B,
+    C,
+    D,
+}
+
+impl Register {
+    pub const ALL: [Register; 4] = [Register::A, Register::B, Register::C, Register::D];
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FromLocation {
+    Int(i32),
+    Reg(Register),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    Inc(Register),
+    Dec(Register),
+    Cpy(FromLocation, Register),
+    Jnz(FromLocation, FromLocation),
+    Tgl(FromLocation),
+    Out(FromLocation),
+    Invalid(Invalid),
+    /// An instruction of an opcode registered with `InstructionSet::with`.
+    Extension(Extension),
+}
+
+/// An instruction produced by `tgl` whose operands don't fit its mnemonic.
+///
+/// Invalid instructions are skipped when executed. They keep their operands,
+/// so toggling one again leads back to a valid instruction, for example
+/// `jnz 1 2` toggles to `cpy 1 2`, which toggles back to `jnz 1 2`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Invalid {
+    /// `inc` of an integer.
+    Inc(i32),
+    /// `dec` of an integer.
+    Dec(i32),
+    /// `cpy` to an integer.
+    Cpy(FromLocation, i32),
+}
+
+impl Instruction {
+    /// The instruction of `opcode` with `operands`.
+    ///
+    /// Operands which don't fit the kinds of a built-in opcode make an
+    /// `Invalid` instruction.
+    pub fn new(opcode: &'static dyn Opcode, operands: Vec<FromLocation>) -> Instruction {
+        opcode.build(&operands).unwrap_or_else(|| Instruction::Extension(Extension::new(opcode, operands)))
+    }
+
+    pub fn opcode(&self) -> &'static dyn Opcode {
+        match *self {
+            Instruction::Inc(_) => &opcode::Inc,
+            Instruction::Dec(_) => &opcode::Dec,
+            Instruction::Cpy(_, _) => &opcode::Cpy,
+            Instruction::Jnz(_, _) => &opcode::Jnz,
+            Instruction::Tgl(_) => &opcode::Tgl,
+            Instruction::Out(_) => &opcode::Out,
+            Instruction::Invalid(Invalid::Inc(_)) => &opcode::Inc,
+            Instruction::Invalid(Invalid::Dec(_)) => &opcode::Dec,
+            Instruction::Invalid(Invalid::Cpy(_, _)) => &opcode::Cpy,
+            Instruction::Extension(ref extension) => extension.opcode(),
+        }
+    }
+
+    pub fn operands(&self) -> Vec<FromLocation> {
+        match *self {
+            Instruction::Inc(reg) | Instruction::Dec(reg) => vec![FromLocation::Reg(reg)],
+            Instruction::Cpy(ref from, reg) => vec![from.clone(), FromLocation::Reg(reg)],
+            Instruction::Jnz(ref value, ref offset) => vec![value.clone(), offset.clone()],
+            Instruction::Tgl(ref value) | Instruction::Out(ref value) => vec![value.clone()],
+            Instruction::Invalid(Invalid::Inc(value)) | Instruction::Invalid(Invalid::Dec(value)) => {
+                vec![FromLocation::Int(value)]
+            }
+            Instruction::Invalid(Invalid::Cpy(ref from, value)) => vec![from.clone(), FromLocation::Int(value)],
+            Instruction::Extension(ref extension) => extension.operands().to_vec(),
+        }
+    }
+
+    /// Calls `f` with the operands, without allocating for built-in instructions.
+    pub fn with_operands<R, F: FnOnce(&[FromLocation]) -> R>(&self, f: F) -> R {
+        match *self {
+            Instruction::Inc(reg) | Instruction::Dec(reg) => f(&[FromLocation::Reg(reg)]),
+            Instruction::Cpy(ref from, reg) => f(&[from.clone(), FromLocation::Reg(reg)]),
+            Instruction::Jnz(ref value, ref offset) => f(&[value.clone(), offset.clone()]),
+            Instruction::Tgl(ref value) | Instruction::Out(ref value) => f(::std::slice::from_ref(value)),
+            Instruction::Invalid(Invalid::Inc(value)) | Instruction::Invalid(Invalid::Dec(value)) => {
+                f(&[FromLocation::Int(value)])
+            }
+            Instruction::Invalid(Invalid::Cpy(ref from, value)) => f(&[from.clone(), FromLocation::Int(value)]),
+            Instruction::Extension(ref extension) => f(extension.operands()),
+        }
+    }
+
+    /// The instruction `tgl` turns this one into.
+    ///
+    /// `inc` and `dec` as well as `cpy` and `jnz` turn into each other, so
+    /// toggling them twice gives back the same instruction. Other instructions
+    /// with one operand become `inc`, those with two become `jnz`.
+    pub fn toggle(self) -> Instruction {
+        self.opcode().toggle(self.operands()).unwrap_or(self)
+    }
+
+    pub fn is_invalid(&self) -> bool {
+        matches!(*self, Instruction::Invalid(_))
+    }
+}
+
+impl Display for Register {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let name = match *self {
+            Register::A => "a",
+            Register::B => "b",
+            Register::C => "c",
+            Register::D => "d",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Display for FromLocation {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            FromLocation::Int(value) => write!(f, "{}", value),
+            FromLocation::Reg(reg) => write!(f, "{}", reg),
+        }
+    }
+}
+
+/// Formats the instruction as source, which parses back to the same instruction.
+///
+/// Invalid instructions are prefixed with `invalid`, as in `invalid cpy 1 2`.
+impl Display for Instruction {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.is_invalid() {
+            write!(f, "invalid ")?;
+        }
+        write!(f, "{}", self.opcode().mnemonic())?;
+        for operand in self.operands() {
+            write!(f, " {}", operand)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Instruction {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse::parse_line(&InstructionSet::full(), 1, s)
+    }
+}
+
+impl FromStr for FromLocation {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse::parse_location(s)
+    }
+}
+
+impl FromStr for Register {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse::parse_register(s)
+    }
+}
+
+/// The instructions a program is allowed to use.
+///
+/// The base set is cpy, inc, dec and jnz (puzzle 12). Extensions such as tgl
+/// (puzzle 23), out (clock signal programs) or opcodes defined outside of
+/// this crate have to be enabled explicitly.
+#[derive(Clone)]
+pub struct InstructionSet {
+    opcodes: Vec<&'static dyn Opcode>,
+}
+
+impl fmt::Debug for InstructionSet {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_list().entries(self.opcodes.iter().map(|o| o.mnemonic())).finish()
+    }
+}
+
+impl InstructionSet {
+    pub fn base() -> InstructionSet {
+        InstructionSet { opcodes: vec![&opcode::Cpy, &opcode::Inc, &opcode::Dec, &opcode::Jnz] }
+    }
+
+    /// Every instruction this crate knows.
+    pub fn full() -> InstructionSet {
+        InstructionSet::base().with_tgl().with_out()
+    }
+
+    /// Adds tgl, which also allows the invalid instructions it produces.
+    pub fn with_tgl(self) -> InstructionSet {
+        self.with(&opcode::Tgl)
+    }
+
+    pub fn with_out(self) -> InstructionSet {
+        self.with(&opcode::Out)
+    }
+
+    /// Adds `opcode`, replacing an opcode with the same mnemonic.
+    pub fn with(mut self, opcode: &'static dyn Opcode) -> InstructionSet {
+        self.opcodes.retain(|o| o.mnemonic() != opcode.mnemonic());
+        self.opcodes.push(opcode);
+        self
+    }
+
+    /// The opcode with this mnemonic, if it's part of the set.
+    pub fn opcode(&self, mnemonic: &str) -> Option<&'static dyn Opcode> {
+        self.opcodes.iter().find(|o| o.mnemonic() == mnemonic).cloned()
+    }
+
+    pub fn supports(&self, instruction: &Instruction) -> bool {
+        let mnemonic = if instruction.is_invalid() { "tgl" } else { instruction.opcode().mnemonic() };
+        self.opcode(mnemonic).is_some()
+    }
+
+    pub fn parse(&self, s: &str) -> Result<Instruction, ParseError> {
+        parse::parse_line(self, 1, s)
+    }
+
+    /// Parses source with labels and comments, see `parse::assemble_program`.
+    pub fn assemble(&self, input: &str) -> Result<Vec<Instruction>, Vec<ParseError>> {
+        parse::assemble_program(self, input)
+    }
+
+    /// Reads the file at `path` and assembles it.
+    pub fn load(&self, path: &str) -> Result<Vec<Instruction>, parse::LoadError> {
+        parse::load_program_with(self, path)
+    }
+
+    /// Parses one instruction per line, collecting the errors of all lines.
+    pub fn parse_program(&self, input: &str) -> Result<Vec<Instruction>, Vec<ParseError>> {
+        let mut code = Vec::new();
+        let mut errors = Vec::new();
+        for (k, v) in input.lines().enumerate() {
+            match parse::parse_line(self, k + 1, v) {
+                Ok(instruction) => code.push(instruction),
+                Err(e) => errors.push(e),
+            }
+        }
+        if errors.is_empty() {
+            Ok(code)
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parse::ParseErrorKind;
+
+    #[test]
+    fn parse_cpy() {
+        let instruction: Instruction = "cpy 41 a".parse().unwrap();
+        assert_eq!(instruction, Instruction::Cpy(FromLocation::Int(41), Register::A));
+    }
+
+    #[test]
+    fn parse_inc() {
+        let instruction: Instruction = "inc a".parse().unwrap();
+        assert_eq!(instruction, Instruction::Inc(Register::A));
+    }
+
+    #[test]
+    fn parse_dec() {
+        let instruction: Instruction = "dec a".parse().unwrap();
+        assert_eq!(instruction, Instruction::Dec(Register::A));
+    }
+
+    #[test]
+    fn parse_jnz() {
+        let instruction: Instruction = "jnz a 2".parse().unwrap();
+        assert_eq!(instruction, Instruction::Jnz(FromLocation::Reg(Register::A), FromLocation::Int(2)));
+    }
+
+    #[test]
+    fn parse_jnz_2() {
+        let instruction: Instruction = "jnz 1 5".parse().unwrap();
+        assert_eq!(instruction, Instruction::Jnz(FromLocation::Int(1), FromLocation::Int(5)));
+    }
+
+    #[test]
+    fn parse_tgl() {
+        let instruction: Instruction = "tgl c".parse().unwrap();
+        assert_eq!(instruction, Instruction::Tgl(FromLocation::Reg(Register::C)));
+    }
+
+    #[test]
+    fn base_set_rejects_tgl() {
+        assert!(InstructionSet::base().parse("tgl a").is_err());
+        assert!(InstructionSet::base().with_tgl().parse("tgl a").is_ok());
+        assert!(InstructionSet::base().with_tgl().parse("out a").is_err());
+        assert!(InstructionSet::base().with_out().parse("out a").is_ok());
+    }
+
+    #[test]
+    fn parse_out() {
+        let instruction: Instruction = "out b".parse().unwrap();
+        assert_eq!(instruction, Instruction::Out(FromLocation::Reg(Register::B)));
+        assert_eq!(instruction.toggle(), Instruction::Inc(Register::B));
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Instruction::Cpy(FromLocation::Int(-41), Register::A).to_string(), "cpy -41 a");
+        assert_eq!(Instruction::Jnz(FromLocation::Reg(Register::C), FromLocation::Int(2)).to_string(), "jnz c 2");
+        assert_eq!(Instruction::Tgl(FromLocation::Reg(Register::D)).to_string(), "tgl d");
+        assert_eq!(Instruction::Invalid(Invalid::Cpy(FromLocation::Int(1), 2)).to_string(), "invalid cpy 1 2");
+    }
+
+    #[test]
+    fn display_round_trip() {
+        let code = vec![
+            Instruction::Inc(Register::A),
+            Instruction::Dec(Register::B),
+            Instruction::Cpy(FromLocation::Reg(Register::C), Register::D),
+            Instruction::Jnz(FromLocation::Int(0), FromLocation::Reg(Register::A)),
+            Instruction::Tgl(FromLocation::Int(-3)),
+            Instruction::Out(FromLocation::Reg(Register::B)),
+            Instruction::Invalid(Invalid::Inc(-3)),
+            Instruction::Invalid(Invalid::Dec(0)),
+            Instruction::Invalid(Invalid::Cpy(FromLocation::Reg(Register::A), 7)),
+        ];
+        for instruction in code {
+            assert_eq!(instruction.to_string().parse(), Ok(instruction));
+        }
+    }
+
+    /// Every operand which makes a difference to toggling.
+    fn operands() -> Vec<FromLocation> {
+        let mut operands: Vec<_> = Register::ALL.iter().map(|&reg| FromLocation::Reg(reg)).collect();
+        operands.extend([-2, 0, 1].iter().map(|&value| FromLocation::Int(value)));
+        operands
+    }
+
+    /// Every built-in instruction with every combination of operands, including the invalid ones.
+    fn instructions() -> Vec<Instruction> {
+        let mut instructions = Vec::new();
+        for &opcode in opcode::BUILTIN.iter() {
+            if opcode.operands().len() == 1 {
+                for a in operands() {
+                    instructions.push(Instruction::new(opcode, vec![a]));
+                }
+            } else {
+                for a in operands() {
+                    for b in operands() {
+                        instructions.push(Instruction::new(opcode, vec![a.clone(), b]));
+                    }
+                }
+            }
+        }
+        instructions
+    }
+
+    #[test]
+    fn toggle_shapes() {
+        let toggle = |source: &str| source.parse::<Instruction>().unwrap().toggle().to_string();
+        assert_eq!(toggle("inc a"), "dec a");
+        assert_eq!(toggle("dec a"), "inc a");
+        assert_eq!(toggle("tgl a"), "inc a");
+        assert_eq!(toggle("tgl 5"), "invalid inc 5");
+        assert_eq!(toggle("out b"), "inc b");
+        assert_eq!(toggle("out 0"), "invalid inc 0");
+        assert_eq!(toggle("cpy a b"), "jnz a b");
+        assert_eq!(toggle("cpy 41 b"), "jnz 41 b");
+        assert_eq!(toggle("jnz a b"), "cpy a b");
+        assert_eq!(toggle("jnz 1 2"), "invalid cpy 1 2");
+        assert_eq!(toggle("invalid cpy 1 2"), "jnz 1 2");
+        assert_eq!(toggle("invalid inc 5"), "invalid dec 5");
+        assert_eq!(toggle("invalid dec 5"), "invalid inc 5");
+    }
+
+    #[test]
+    fn toggle_twice_is_identity() {
+        for instruction in instructions() {
+            let toggled = instruction.clone().toggle();
+            assert_eq!(toggled.operands(), instruction.operands(), "{}", instruction);
+            match instruction {
+                Instruction::Tgl(_) | Instruction::Out(_) => {
+                    assert_eq!(toggled, Instruction::new(&opcode::Inc, instruction.operands()));
+                }
+                _ => assert_eq!(toggled.clone().toggle(), instruction, "{}", instruction),
+            }
+            assert_eq!(toggled.clone().toggle().toggle(), toggled, "{}", instruction);
+        }
+    }
+
+    #[test]
+    fn invalid_instructions() {
+        let instructions = instructions();
+        let invalid: Vec<_> = instructions.iter().filter(|i| i.is_invalid()).collect();
+        // inc and dec of 3 integers, cpy of 7 operands to 3 integers
+        assert_eq!(invalid.len(), 3 + 3 + 7 * 3);
+        for instruction in invalid {
+            assert!(instruction.operands().iter().any(|o| matches!(*o, FromLocation::Int(_))));
+            assert!(InstructionSet::base().parse(&instruction.to_string()).is_err());
+        }
+        for instruction in instructions {
+            if !instruction.is_invalid() {
+                let marked = InstructionSet::full().parse(&format!("invalid {}", instruction));
+                assert_eq!(marked.map_err(|e| e.kind), Err(ParseErrorKind::NotInvalid), "{}", instruction);
+            }
+            assert_eq!(InstructionSet::full().parse(&instruction.to_string()), Ok(instruction));
+        }
+    }
+}
@@ -0,0 +1,24 @@
+//! The assembunny virtual machine shared by the puzzles of day 12 and day 23.
+
+mod instruction;
+pub mod cfg;
+pub mod clock;
+pub mod debugger;
+mod decode;
+pub mod disasm;
+mod machine;
+pub mod opcode;
+pub mod optimizer;
+pub mod output;
+mod parse;
+mod snapshot;
+pub mod symbolic;
+pub mod trace;
+pub mod translate;
+mod word;
+
+pub use instruction::{FromLocation, Instruction, InstructionSet, Register};
+pub use machine::{Halt, Machine, MachineConfig, Outcome};
+pub use snapshot::Snapshot;
+pub use word::Word;
+pub use parse::{assemble, load_program, parse_program, LoadError, ParseError, ParseErrorKind, ParseErrors};
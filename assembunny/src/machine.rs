@@ -0,0 +1,1039 @@
+use std::collections::VecDeque;
+
+use decode::{self, Decoded, Fused, Operand};
+use instruction::{FromLocation, Instruction, Register};
+use opcode::Cpu;
+use optimizer::{self, Op};
+use output::{Discard, Sink};
+use snapshot::Snapshot;
+use trace::{Executed, Step, Toggle};
+use word::Word;
+
+/// How a call to `Machine::run_with_limit` ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Halted(Halt),
+    StepLimitReached,
+    /// The machine reached a state it was in before, so it will never halt.
+    LoopDetected,
+}
+
+/// Why a machine stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Halt {
+    /// Execution continued past the last instruction.
+    EndOfProgram,
+    /// A jump at `from` targeted a pc outside of the program.
+    JumpOutOfRange { from: usize, target: i64 },
+    /// The instruction at `pc` overflowed a register while overflow checks
+    /// were enabled. The instruction was not executed.
+    Overflow { pc: usize },
+}
+
+/// What `Machine::step_back` needs to undo a step.
+#[derive(Clone, Debug)]
+struct Undo<W> {
+    pc: i64,
+    registers: [W; 4],
+    steps: u64,
+    /// The position and old instruction if the step toggled one.
+    toggled: Option<(usize, Instruction)>,
+}
+
+/// An assembunny machine with registers of type `W`.
+///
+/// Arithmetic wraps around unless overflow checks are enabled. The code is
+/// executed in its decoded form, see `decode`.
+#[derive(Debug, Default)]
+pub struct Machine<W: Word = i32> {
+    registers: [W; 4],
+    pc: i64,
+    code: Vec<Instruction>,
+    /// `code` decoded, re-decoded wherever `code` changes.
+    decoded: Vec<Decoded<W>>,
+    halt: Option<Halt>,
+    /// The target of a jump taken by the instruction being executed.
+    jump: Option<i64>,
+    check_overflow: bool,
+    ops: Option<Vec<Option<Op>>>,
+    /// `ops` decoded, empty while the optimizer is disabled.
+    fused: Vec<Option<Fused<W>>>,
+    trace: Option<Vec<Step<W>>>,
+    last_toggle: Option<(usize, Instruction)>,
+    steps: u64,
+    history: VecDeque<Undo<W>>,
+    history_depth: usize,
+}
+
+/// The initial state of a machine and how long it may run.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MachineConfig<W: Word = i32> {
+    registers: [W; 4],
+    pc: i64,
+    max_steps: Option<u64>,
+    check_overflow: bool,
+}
+
+impl MachineConfig {
+    /// A configuration for `i32` registers, use `default` for other widths.
+    pub fn new() -> MachineConfig {
+        MachineConfig::default()
+    }
+}
+
+impl<W: Word> MachineConfig<W> {
+    pub fn reg(mut self, reg: Register, value: W) -> MachineConfig<W> {
+        self.registers[reg as usize] = value;
+        self
+    }
+
+    pub fn pc(mut self, pc: i64) -> MachineConfig<W> {
+        self.pc = pc;
+        self
+    }
+
+    pub fn max_steps(mut self, max_steps: u64) -> MachineConfig<W> {
+        self.max_steps = Some(max_steps);
+        self
+    }
+
+    pub fn check_overflow(mut self, check_overflow: bool) -> MachineConfig<W> {
+        self.check_overflow = check_overflow;
+        self
+    }
+
+    /// Creates a machine in the configured state.
+    pub fn build(&self, code: Vec<Instruction>) -> Machine<W> {
+        Machine {
+            registers: self.registers,
+            pc: self.pc,
+            check_overflow: self.check_overflow,
+            ..Machine::from_code(code)
+        }
+    }
+
+    /// Runs the machine until it halts, loops or exceeds the step limit.
+    pub fn run(&self, machine: &mut Machine<W>) -> Outcome {
+        machine.run_with_limit(self.max_steps.unwrap_or(u64::MAX))
+    }
+
+    /// Runs `code` with the optimizer from the configured state and returns register a.
+    ///
+    /// Fails if the program doesn't halt within the step limit, loops forever
+    /// or overflows a register. Jumps out of the program halt it normally.
+    pub fn run_program(&self, code: Vec<Instruction>) -> Result<W, Outcome> {
+        let mut machine = self.build(code);
+        machine.enable_optimizer();
+        match self.run(&mut machine) {
+            outcome @ Outcome::Halted(Halt::Overflow { .. }) |
+            outcome @ Outcome::StepLimitReached |
+            outcome @ Outcome::LoopDetected => Err(outcome),
+            Outcome::Halted(_) => Ok(machine.get_reg(Register::A)),
+        }
+    }
+}
+
+impl Machine {
+    /// A machine with `i32` registers, use `from_code` for other widths.
+    pub fn new(code: Vec<Instruction>) -> Machine {
+        Machine::from_code(code)
+    }
+
+    pub fn toggle_instruction(instruction: Instruction) -> Instruction {
+        instruction.toggle()
+    }
+}
+
+impl<W: Word> Machine<W> {
+
+    pub fn from_code(code: Vec<Instruction>) -> Machine<W> {
+        Machine { decoded: code.iter().map(decode::decode).collect(), code, ..Default::default() }
+    }
+
+    /// Replaces add and multiply loops with native arithmetic.
+    ///
+    /// Ops are re-derived whenever `tgl` changes an instruction they cover.
+    pub fn enable_optimizer(&mut self) {
+        let ops = optimizer::optimize(&self.code);
+        self.fused = ops.iter().map(|op| op.as_ref().map(decode::decode_op)).collect();
+        self.ops = Some(ops);
+    }
+
+    pub fn disable_optimizer(&mut self) {
+        self.ops = None;
+        self.fused.clear();
+    }
+
+    pub fn optimizer_enabled(&self) -> bool {
+        self.ops.is_some()
+    }
+
+    /// Halts with `Halt::Overflow` instead of wrapping around.
+    pub fn enable_overflow_check(&mut self) {
+        self.check_overflow = true;
+    }
+
+    /// Records every executed step until the trace is taken.
+    pub fn enable_trace(&mut self) {
+        self.trace = Some(Vec::new());
+    }
+
+    /// Keeps the last `depth` steps so they can be undone with `step_back`.
+    ///
+    /// A depth of 0 disables the history.
+    pub fn enable_history(&mut self, depth: usize) {
+        self.history_depth = depth;
+        while self.history.len() > depth {
+            self.history.pop_front();
+        }
+    }
+
+    /// Returns the steps recorded since the last call.
+    pub fn take_trace(&mut self) -> Vec<Step<W>> {
+        match self.trace {
+            Some(ref mut trace) => ::std::mem::take(trace),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn run(&mut self) {
+        while self.step(&mut Discard) {
+        }
+    }
+
+    /// Runs for at most `max_steps` steps.
+    ///
+    /// Loops are found with Brent's algorithm: the state is saved after 1, 2,
+    /// 4, 8, ... steps and compared with the current one after each step, so
+    /// every loop is detected within a few times its length.
+    pub fn run_with_limit(&mut self, max_steps: u64) -> Outcome {
+        if let Some(halt) = self.halt_reason() {
+            return Outcome::Halted(halt);
+        }
+        let mut saved = (self.pc, self.registers, self.code.clone());
+        let mut power = 1;
+        let mut length = 0;
+        for _ in 0..max_steps {
+            if !self.step(&mut Discard) {
+                return Outcome::Halted(self.halt_reason().unwrap());
+            }
+            if self.pc == saved.0 && self.registers == saved.1 && self.code == saved.2 {
+                return Outcome::LoopDetected;
+            }
+            length += 1;
+            if length == power {
+                saved = (self.pc, self.registers, self.code.clone());
+                power *= 2;
+                length = 0;
+            }
+        }
+        Outcome::StepLimitReached
+    }
+
+    /// Runs until `n` values were output and returns them.
+    ///
+    /// Fails with the reason the machine halted if that happens first.
+    pub fn run_until_outputs(&mut self, n: usize) -> Result<Vec<W>, Halt> {
+        let mut output = Vec::with_capacity(n);
+        while output.len() < n {
+            if !self.step(&mut output) {
+                return Err(self.halt_reason().unwrap());
+            }
+        }
+        Ok(output)
+    }
+
+    /// Executes the instruction at pc, dropping any output.
+    ///
+    /// Returns `None` once the machine halted, see `halt_reason`.
+    pub fn execute(&mut self) -> Option<()> {
+        self.execute_with(&mut Discard)
+    }
+
+    /// Executes the instruction at pc, sending output to `sink`.
+    pub fn execute_with(&mut self, sink: &mut dyn Sink<W>) -> Option<()> {
+        if self.step(sink) {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    /// Executes the instruction at pc, returns `false` once the machine halted.
+    fn step(&mut self, sink: &mut dyn Sink<W>) -> bool {
+        if self.halt_reason().is_some() {
+            return false;
+        }
+        let pc = self.pc as usize;
+        let registers = self.registers;
+        let (executed, slots) = if let Some(slots) = self.execute_op(pc) {
+            let op = self.trace.as_ref().and_then(|_| self.ops.as_ref()?[pc].clone());
+            (op.map(Executed::Op), slots)
+        } else {
+            // only the trace needs the instruction, as it was before a `tgl` changes it
+            let instruction = self.trace.as_ref().map(|_| self.code[pc].clone());
+            self.execute_instruction(pc, sink);
+            (instruction.map(Executed::Instruction), 1)
+        };
+        let next = self.jump.take().unwrap_or((pc + slots) as i64);
+        if self.history_depth > 0 {
+            if self.history.len() == self.history_depth {
+                self.history.pop_front();
+            }
+            let toggled = self.last_toggle.clone();
+            self.history.push_back(Undo { pc: pc as i64, registers, steps: self.steps, toggled });
+        }
+        if self.trace.is_none() {
+            self.last_toggle = None;
+        }
+        if self.halt.is_some() {
+            return false;
+        }
+        if let Some(executed) = executed {
+            self.record(pc, registers, executed);
+        }
+        self.steps += 1;
+        self.pc = next;
+        if self.index().is_some() {
+            return true;
+        }
+        if next != (pc + slots) as i64 {
+            self.halt = Some(Halt::JumpOutOfRange { from: pc, target: next });
+        }
+        false
+    }
+
+    /// The index of the instruction at pc, if pc is inside the program.
+    fn index(&self) -> Option<usize> {
+        if self.pc >= 0 && (self.pc as usize) < self.code.len() {
+            Some(self.pc as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Why the machine stopped, or `None` if it can continue.
+    pub fn halt_reason(&self) -> Option<Halt> {
+        if let Some(halt @ Halt::Overflow { .. }) = self.halt {
+            return Some(halt);
+        }
+        match self.index() {
+            Some(_) => None,
+            None => Some(self.halt.unwrap_or(Halt::EndOfProgram)),
+        }
+    }
+
+    fn record(&mut self, pc: usize, registers: [W; 4], executed: Executed) {
+        let changes = Register::ALL.iter().zip(registers.iter())
+            .filter(|&(&reg, &old)| self.get_reg(reg) != old)
+            .map(|(&reg, &old)| (reg, old, self.get_reg(reg)))
+            .collect();
+        let toggle = self.last_toggle.take().map(|(position, old)| Toggle {
+            position,
+            old,
+            new: self.code[position].clone(),
+        });
+        let step = Step { pc, executed, changes, toggle };
+        self.trace.as_mut().unwrap().push(step);
+    }
+
+    /// Executes the fused op at pc, if there is one and its preconditions hold.
+    ///
+    /// Returns the number of instructions the op replaced.
+    fn execute_op(&mut self, pc: usize) -> Option<usize> {
+        let fused = (*self.fused.get(pc)?)?;
+        let zero = W::from_i32(0);
+        match fused {
+            Fused::Add { dst, src } => {
+                let count = self.registers[src];
+                if count <= zero {
+                    return None;
+                }
+                if let Some(sum) = self.add(self.registers[dst], count) {
+                    self.registers[dst] = sum;
+                    self.registers[src] = zero;
+                }
+            }
+            Fused::Mul { dst, factor, inner, outer } => {
+                let factor = self.value(factor);
+                let count = self.registers[outer];
+                if factor <= zero || count <= zero {
+                    return None;
+                }
+                let product = self.mul(factor, count);
+                if let Some(sum) = product.and_then(|p| self.add(self.registers[dst], p)) {
+                    self.registers[dst] = sum;
+                    self.registers[inner] = zero;
+                    self.registers[outer] = zero;
+                }
+            }
+        }
+        Some(fused.slots())
+    }
+
+    fn execute_instruction(&mut self, pc: usize, sink: &mut dyn Sink<W>) {
+        let (zero, one) = (W::from_i32(0), W::from_i32(1));
+        match self.decoded[pc] {
+            Decoded::Inc(reg) => {
+                if let Some(value) = self.add(self.registers[reg], one) {
+                    self.registers[reg] = value;
+                }
+            }
+            Decoded::Dec(reg) => {
+                if let Some(value) = self.sub(self.registers[reg], one) {
+                    self.registers[reg] = value;
+                }
+            }
+            Decoded::Cpy(src, dst) => self.registers[dst] = self.value(src),
+            Decoded::Jump(offset) => {
+                let offset = self.value(offset).to_offset();
+                self.jump(offset);
+            }
+            Decoded::Jnz(reg, offset) => {
+                if self.registers[reg] != zero {
+                    let offset = self.value(offset).to_offset();
+                    self.jump(offset);
+                }
+            }
+            Decoded::Tgl(offset) => {
+                let offset = self.value(offset).to_offset();
+                self.toggle(offset);
+            }
+            Decoded::Out(value) => sink.emit(self.value(value)),
+            Decoded::Nop => (),
+            Decoded::Extension => {
+                let inst = self.code[pc].clone();
+                inst.with_operands(|operands| inst.opcode().execute(operands, &mut Context { machine: self, sink }));
+            }
+        }
+    }
+
+    #[inline]
+    fn value(&self, operand: Operand<W>) -> W {
+        match operand {
+            Operand::Imm(value) => value,
+            Operand::Reg(reg) => self.registers[reg],
+        }
+    }
+
+    pub fn toggle(&mut self, offset: i64) {
+        let position = self.pc.saturating_add(offset);
+        if position < 0 || position as usize >= self.code.len() {
+            return
+        }
+        let position = position as usize;
+        if self.trace.is_some() || self.history_depth > 0 {
+            self.last_toggle = Some((position, self.code[position].clone()));
+        }
+        self.code[position] = self.code[position].clone().toggle();
+        self.redecode(position);
+    }
+
+    /// Decodes `code[position]` again, along with the ops covering it.
+    fn redecode(&mut self, position: usize) {
+        self.decoded[position] = decode::decode(&self.code[position]);
+        if let Some(ref mut ops) = self.ops {
+            for pc in optimizer::reoptimize(&self.code, ops, position) {
+                self.fused[pc] = ops[pc].as_ref().map(decode::decode_op);
+            }
+        }
+    }
+
+    /// Undoes the last step, including an instruction it toggled.
+    ///
+    /// Returns `false` if the history is empty, see `enable_history`. The
+    /// trace is not affected.
+    pub fn step_back(&mut self) -> bool {
+        let undo = match self.history.pop_back() {
+            Some(undo) => undo,
+            None => return false,
+        };
+        self.pc = undo.pc;
+        self.registers = undo.registers;
+        self.steps = undo.steps;
+        self.halt = None;
+        if let Some((position, old)) = undo.toggled {
+            self.code[position] = old;
+            self.redecode(position);
+        }
+        true
+    }
+
+    /// The number of steps `step_back` can undo.
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn pc(&self) -> i64 {
+        self.pc
+    }
+
+    /// The number of steps executed so far, a fused op counts as one step.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// The registers, pc, code and step counter, see `restore`.
+    pub fn snapshot(&self) -> Snapshot<W> {
+        Snapshot { registers: self.registers, pc: self.pc, code: self.code.clone(), steps: self.steps }
+    }
+
+    /// Continues from a snapshot, which may come from a different machine.
+    ///
+    /// The optimizer, overflow check and tracing stay as they are. A halt
+    /// because of an overflow or a jump out of the program is forgotten.
+    pub fn restore(&mut self, snapshot: &Snapshot<W>) {
+        self.registers = snapshot.registers;
+        self.pc = snapshot.pc;
+        self.code = snapshot.code.clone();
+        self.decoded = self.code.iter().map(decode::decode).collect();
+        self.steps = snapshot.steps;
+        self.halt = None;
+        self.last_toggle = None;
+        self.history.clear();
+        if self.ops.is_some() {
+            self.enable_optimizer();
+        }
+    }
+
+    /// The current code, including the changes made by `tgl`.
+    pub fn code(&self) -> &[Instruction] {
+        &self.code
+    }
+
+    pub fn get_reg(&self, reg: Register) -> W {
+        self.registers[reg as usize]
+    }
+
+    fn get_value(&self, location: &FromLocation) -> W {
+        match *location {
+            FromLocation::Int(i) => W::from_i32(i),
+            FromLocation::Reg(reg) => self.get_reg(reg),
+        }
+    }
+
+    pub fn get_reg_mut(&mut self, reg: Register) -> &mut W {
+        &mut self.registers[reg as usize]
+    }
+
+    /// Adds two values, halting the machine on overflow if overflow checks are enabled.
+    fn add(&mut self, a: W, b: W) -> Option<W> {
+        if self.check_overflow {
+            self.checked(a.checked_add(b))
+        } else {
+            Some(a.wrapping_add(b))
+        }
+    }
+
+    fn sub(&mut self, a: W, b: W) -> Option<W> {
+        if self.check_overflow {
+            self.checked(a.checked_sub(b))
+        } else {
+            Some(a.wrapping_sub(b))
+        }
+    }
+
+    fn mul(&mut self, a: W, b: W) -> Option<W> {
+        if self.check_overflow {
+            self.checked(a.checked_mul(b))
+        } else {
+            Some(a.wrapping_mul(b))
+        }
+    }
+
+    fn checked(&mut self, result: Option<W>) -> Option<W> {
+        if result.is_none() {
+            self.halt = Some(Halt::Overflow { pc: self.pc as usize });
+        }
+        result
+    }
+
+    pub fn jnz(&mut self, value: FromLocation, offset: i64) {
+        if self.get_value(&value) != W::from_i32(0) {
+            self.jump(offset);
+        }
+    }
+
+    /// Continues `offset` instructions away from pc once the current step ends.
+    ///
+    /// The target saturates, anything outside of the program halts with
+    /// `Halt::JumpOutOfRange`.
+    fn jump(&mut self, offset: i64) {
+        self.jump = Some(self.pc.saturating_add(offset));
+    }
+
+    pub fn copy(&mut self, src: FromLocation, dst: Register) {
+        *self.get_reg_mut(dst) = self.get_value(&src);
+    }
+
+    pub fn decrement(&mut self, reg: Register) {
+        if let Some(value) = self.sub(self.get_reg(reg), W::from_i32(1)) {
+            *self.get_reg_mut(reg) = value;
+        }
+    }
+
+    pub fn increment(&mut self, reg: Register) {
+        if let Some(value) = self.add(self.get_reg(reg), W::from_i32(1)) {
+            *self.get_reg_mut(reg) = value;
+        }
+    }
+}
+
+/// A machine executing an instruction, see `Opcode::execute`.
+struct Context<'a, W: Word> {
+    machine: &'a mut Machine<W>,
+    sink: &'a mut dyn Sink<W>,
+}
+
+impl<'a, W: Word> Cpu for Context<'a, W> {
+    fn value(&mut self, location: &FromLocation) -> i64 {
+        self.machine.get_value(location).to_offset()
+    }
+
+    fn is_zero(&mut self, location: &FromLocation) -> bool {
+        self.machine.get_value(location) == W::from_i32(0)
+    }
+
+    fn copy(&mut self, src: &FromLocation, dst: Register) {
+        self.machine.copy(src.clone(), dst);
+    }
+
+    fn add(&mut self, dst: Register, value: &FromLocation) {
+        let machine = &mut *self.machine;
+        if let Some(sum) = machine.add(machine.get_reg(dst), machine.get_value(value)) {
+            *machine.get_reg_mut(dst) = sum;
+        }
+    }
+
+    fn sub(&mut self, dst: Register, value: &FromLocation) {
+        let machine = &mut *self.machine;
+        if let Some(difference) = machine.sub(machine.get_reg(dst), machine.get_value(value)) {
+            *machine.get_reg_mut(dst) = difference;
+        }
+    }
+
+    fn mul(&mut self, dst: Register, value: &FromLocation) {
+        let machine = &mut *self.machine;
+        if let Some(product) = machine.mul(machine.get_reg(dst), machine.get_value(value)) {
+            *machine.get_reg_mut(dst) = product;
+        }
+    }
+
+    fn jump(&mut self, offset: &FromLocation) {
+        let offset = self.machine.get_value(offset).to_offset();
+        self.machine.jump(offset);
+    }
+
+    fn toggle(&mut self, offset: &FromLocation) {
+        let offset = self.machine.get_value(offset).to_offset();
+        self.machine.toggle(offset);
+    }
+
+    fn output(&mut self, value: &FromLocation) {
+        self.sink.emit(self.machine.get_value(value));
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use instruction::InstructionSet;
+
+    #[test]
+    fn test_toggle_instruction_inc() {
+        let instruction = Instruction::Inc(Register::A);
+        assert_eq!(Machine::toggle_instruction(instruction), Instruction::Dec(Register::A));
+    }
+
+    #[test]
+    fn test_toggle_instruction_dec() {
+        let instruction = Instruction::Dec(Register::A);
+        assert_eq!(Machine::toggle_instruction(instruction), Instruction::Inc(Register::A));
+    }
+
+    #[test]
+    fn toggle_back_restores_jump() {
+        let mut machine = machine("tgl 3\ntgl 2\ninc b\njnz 1 2\ninc c");
+        machine.run();
+        assert_eq!(machine.code()[3], Instruction::Jnz(FromLocation::Int(1), FromLocation::Int(2)));
+        assert_eq!(machine.get_reg(Register::B), 1);
+        assert_eq!(machine.get_reg(Register::C), 0);
+    }
+
+    #[test]
+    fn toggle_redecodes() {
+        let mut machine = machine("cpy 2 a\ntgl a\ntgl a\ntgl a\ncpy 1 a\ndec a\ndec a");
+        machine.run();
+        assert_eq!(machine.get_reg(Register::A), 3);
+        assert_eq!(machine.pc(), 7);
+    }
+
+    #[test]
+    fn invalid_instructions_are_skipped() {
+        for source in &["invalid inc 1", "invalid dec -1", "invalid cpy a 2", "invalid cpy 3 0"] {
+            let mut machine = machine(source);
+            *machine.get_reg_mut(Register::A) = 7;
+            assert_eq!(machine.execute(), None);
+            assert_eq!(machine.halt_reason(), Some(Halt::EndOfProgram));
+            assert_eq!(machine.get_reg(Register::A), 7);
+            assert_eq!(machine.pc(), 1);
+        }
+    }
+
+    fn machine(input: &str) -> Machine {
+        Machine::new(InstructionSet::full().parse_program(input).unwrap())
+    }
+
+    #[test]
+    fn run_with_limit_halts() {
+        let mut machine = machine("cpy 3 a\ndec a\njnz a -1");
+        assert_eq!(machine.run_with_limit(100), Outcome::Halted(Halt::EndOfProgram));
+        assert_eq!(machine.run_with_limit(100), Outcome::Halted(Halt::EndOfProgram));
+    }
+
+    #[test]
+    fn run_with_limit_detects_self_jump() {
+        let mut machine = machine("jnz 1 0");
+        assert_eq!(machine.run_with_limit(1_000_000), Outcome::LoopDetected);
+    }
+
+    #[test]
+    fn run_with_limit_detects_long_loop() {
+        let mut machine = machine("cpy 100 b\ndec b\njnz b -1\njnz 1 -3");
+        assert_eq!(machine.run_with_limit(1_000_000), Outcome::LoopDetected);
+    }
+
+    #[test]
+    fn run_with_limit_stops_counting_loop() {
+        let mut machine = machine("inc a\njnz 1 -1");
+        assert_eq!(machine.run_with_limit(1000), Outcome::StepLimitReached);
+        assert_eq!(machine.get_reg(Register::A), 500);
+    }
+
+    #[test]
+    fn run_with_limit_sees_code_changes() {
+        // the registers repeat after two steps, but the code only after four
+        let mut machine = machine("tgl 2\njnz 1 -1\ninc a");
+        assert_eq!(machine.run_with_limit(1000), Outcome::LoopDetected);
+    }
+
+    #[test]
+    fn jump_before_start_halts() {
+        let mut machine = machine("inc a\njnz 1 -2\ninc a");
+        assert_eq!(machine.run_with_limit(100), Outcome::Halted(Halt::JumpOutOfRange { from: 1, target: -1 }));
+        assert_eq!(machine.get_reg(Register::A), 1);
+        assert_eq!(machine.execute(), None);
+    }
+
+    #[test]
+    fn jump_on_first_line_halts() {
+        let mut machine = machine("jnz 1 -1");
+        machine.run();
+        assert_eq!(machine.halt_reason(), Some(Halt::JumpOutOfRange { from: 0, target: -1 }));
+    }
+
+    #[test]
+    fn jump_past_end_halts() {
+        let mut machine = machine("jnz 1 7\ninc a");
+        machine.run();
+        assert_eq!(machine.halt_reason(), Some(Halt::JumpOutOfRange { from: 0, target: 7 }));
+        assert_eq!(machine.get_reg(Register::A), 0);
+    }
+
+    #[test]
+    fn jump_by_extreme_offsets_halts() {
+        let code = InstructionSet::full().parse_program("inc b\njnz 1 a").unwrap();
+        for &(offset, target) in &[(i64::MIN, i64::MIN + 1), (i64::MAX, i64::MAX), (-2, -1)] {
+            let config = MachineConfig::default().reg(Register::A, offset);
+            let mut machine = config.build(code.clone());
+            assert_eq!(config.run(&mut machine), Outcome::Halted(Halt::JumpOutOfRange { from: 1, target }));
+            assert_eq!(machine.get_reg(Register::B), 1);
+        }
+        let config = MachineConfig::default().reg(Register::A, i64::MIN);
+        let mut machine = config.build(InstructionSet::full().parse_program("jnz 1 a").unwrap());
+        assert_eq!(config.run(&mut machine), Outcome::Halted(Halt::JumpOutOfRange { from: 0, target: i64::MIN }));
+    }
+
+    #[test]
+    fn empty_program_is_halted() {
+        let mut machine = machine("");
+        assert_eq!(machine.halt_reason(), Some(Halt::EndOfProgram));
+        assert_eq!(machine.execute(), None);
+    }
+
+    #[test]
+    fn toggle_before_start_is_ignored() {
+        let mut machine = machine("tgl -5\ninc a");
+        machine.run();
+        assert_eq!(machine.get_reg(Register::A), 1);
+        assert_eq!(machine.halt_reason(), Some(Halt::EndOfProgram));
+    }
+
+    #[test]
+    fn arithmetic_wraps_by_default() {
+        let mut machine = machine("inc a");
+        *machine.get_reg_mut(Register::A) = i32::MAX;
+        machine.run();
+        assert_eq!(machine.get_reg(Register::A), i32::MIN);
+    }
+
+    #[test]
+    fn overflow_check_halts() {
+        let mut machine = machine("dec b\ndec a\ninc b");
+        *machine.get_reg_mut(Register::A) = i32::MIN;
+        machine.enable_overflow_check();
+        assert_eq!(machine.run_with_limit(10), Outcome::Halted(Halt::Overflow { pc: 1 }));
+        assert_eq!(machine.get_reg(Register::A), i32::MIN);
+        assert_eq!(machine.get_reg(Register::B), -1);
+        assert_eq!(machine.execute(), None);
+    }
+
+    #[test]
+    fn overflow_check_in_optimized_mul() {
+        let input = "cpy 65536 b\ncpy 65536 d\ncpy b c\ninc a\ndec c\njnz c -2\ndec d\njnz d -5";
+        let mut machine = machine(input);
+        machine.enable_optimizer();
+        machine.enable_overflow_check();
+        machine.run();
+        assert_eq!(machine.halt_reason(), Some(Halt::Overflow { pc: 2 }));
+        assert_eq!(machine.get_reg(Register::A), 0);
+
+        let mut machine = Machine::<i64>::from_code(InstructionSet::full().parse_program(input).unwrap());
+        machine.enable_optimizer();
+        machine.enable_overflow_check();
+        machine.run();
+        assert_eq!(machine.halt_reason(), Some(Halt::EndOfProgram));
+        assert_eq!(machine.get_reg(Register::A), 1 << 32);
+    }
+
+    #[test]
+    fn config_sets_initial_state() {
+        let config = MachineConfig::new()
+            .reg(Register::B, 2)
+            .reg(Register::D, 4)
+            .pc(1);
+        let mut machine = config.build(InstructionSet::full().parse_program("inc a\ninc b\ninc d").unwrap());
+        assert_eq!(config.run(&mut machine), Outcome::Halted(Halt::EndOfProgram));
+        assert_eq!(machine.get_reg(Register::A), 0);
+        assert_eq!(machine.get_reg(Register::B), 3);
+        assert_eq!(machine.get_reg(Register::D), 5);
+    }
+
+    #[test]
+    fn config_start_outside_of_program() {
+        let config = MachineConfig::new().pc(-1);
+        let mut machine = config.build(InstructionSet::full().parse_program("inc a").unwrap());
+        assert_eq!(config.run(&mut machine), Outcome::Halted(Halt::EndOfProgram));
+        assert_eq!(machine.get_reg(Register::A), 0);
+    }
+
+    #[test]
+    fn config_step_limit() {
+        let config = MachineConfig::new().max_steps(10);
+        let mut machine = config.build(InstructionSet::full().parse_program("inc a\njnz 1 -1").unwrap());
+        assert_eq!(config.run(&mut machine), Outcome::StepLimitReached);
+        assert_eq!(machine.get_reg(Register::A), 5);
+    }
+
+    #[test]
+    fn config_overflow_check() {
+        let config = MachineConfig::default().reg(Register::A, i64::MAX).check_overflow(true);
+        let mut machine = config.build(InstructionSet::full().parse_program("inc a").unwrap());
+        assert_eq!(config.run(&mut machine), Outcome::Halted(Halt::Overflow { pc: 0 }));
+    }
+
+    #[test]
+    fn config_run_program() {
+        let code = InstructionSet::full().parse_program("cpy 3 a\njnz 1 5").unwrap();
+        assert_eq!(MachineConfig::new().run_program(code), Ok(3));
+        let code = InstructionSet::full().parse_program("inc a").unwrap();
+        let config = MachineConfig::default().reg(Register::A, i64::MAX).check_overflow(true);
+        assert_eq!(config.run_program(code), Err(Outcome::Halted(Halt::Overflow { pc: 0 })));
+        let code = InstructionSet::full().parse_program("jnz 1 0").unwrap();
+        assert_eq!(MachineConfig::new().run_program(code), Err(Outcome::LoopDetected));
+    }
+
+    #[test]
+    fn wide_registers() {
+        let mut machine = Machine::<i128>::from_code(InstructionSet::full().parse_program("inc a\njnz a 2").unwrap());
+        *machine.get_reg_mut(Register::A) = i128::from(i64::MAX);
+        machine.run();
+        assert_eq!(machine.get_reg(Register::A), i128::from(i64::MAX) + 1);
+    }
+
+    fn run_optimized(input: &str) -> Machine {
+        let code = InstructionSet::base().with_tgl().parse_program(input).unwrap();
+        let mut machine = Machine::new(code);
+        machine.enable_optimizer();
+        machine.run();
+        machine
+    }
+
+    #[test]
+    fn optimized_add() {
+        let machine = run_optimized("cpy 3 b\ninc a\ndec b\njnz b -2");
+        assert_eq!(machine.get_reg(Register::A), 3);
+        assert_eq!(machine.get_reg(Register::B), 0);
+    }
+
+    #[test]
+    fn optimized_mul() {
+        let machine = run_optimized(
+"cpy 4 b
+cpy 5 d
+cpy b c
+inc a
+dec c
+jnz c -2
+dec d
+jnz d -5");
+        assert_eq!(machine.get_reg(Register::A), 20);
+        assert_eq!(machine.get_reg(Register::C), 0);
+        assert_eq!(machine.get_reg(Register::D), 0);
+    }
+
+    #[test]
+    fn optimized_add_entered_in_the_middle() {
+        let machine = run_optimized(
+"cpy 3 b
+jnz 1 2
+inc a
+dec b
+jnz b -2");
+        assert_eq!(machine.get_reg(Register::A), 2);
+        assert_eq!(machine.get_reg(Register::B), 0);
+    }
+
+    #[test]
+    fn toggle_invalidates_optimized_loop() {
+        let machine = run_optimized(
+"cpy 3 b
+tgl 3
+inc a
+dec b
+jnz b -2");
+        // the jnz got toggled into an invalid instruction, so the loop body runs once
+        assert_eq!(machine.get_reg(Register::A), 1);
+        assert_eq!(machine.get_reg(Register::B), 2);
+    }
+
+    #[test]
+    fn toggle_creates_optimized_loop() {
+        let code = InstructionSet::base().with_tgl().parse_program(
+"cpy 3 b
+tgl 2
+inc a
+inc b
+jnz b -2").unwrap();
+        let mut machine = Machine::new(code);
+        machine.enable_optimizer();
+        assert_eq!(machine.ops.as_ref().unwrap()[2], None);
+        assert_eq!(machine.fused[2], None);
+        machine.execute();
+        machine.execute();
+        assert_eq!(machine.ops.as_ref().unwrap()[2], Some(Op::Add { dst: Register::A, src: Register::B }));
+        assert_eq!(machine.fused[2], Some(Fused::Add { dst: 0, src: 1 }));
+        machine.run();
+        assert_eq!(machine.get_reg(Register::A), 3);
+        assert_eq!(machine.get_reg(Register::B), 0);
+    }
+
+    #[test]
+    fn output() {
+        let mut machine = machine("cpy 3 a\nout a\ndec a\njnz a -2\nout 7");
+        let mut output = Vec::new();
+        while machine.execute_with(&mut output).is_some() {
+        }
+        assert_eq!(output, vec![3, 2, 1, 7]);
+    }
+
+    #[test]
+    fn run_until_outputs() {
+        let mut counter = machine("out a\ninc a\njnz 1 -2");
+        assert_eq!(counter.run_until_outputs(3), Ok(vec![0, 1, 2]));
+        assert_eq!(counter.run_until_outputs(2), Ok(vec![3, 4]));
+        let mut short = machine("out 1\nout 2");
+        assert_eq!(short.run_until_outputs(3), Err(Halt::EndOfProgram));
+    }
+
+    #[test]
+    fn step_back_undoes_toggle() {
+        let mut machine = machine("tgl 1\ninc a\ninc a");
+        machine.enable_history(10);
+        machine.run();
+        assert_eq!(machine.get_reg(Register::A), 0);
+        assert_eq!(machine.history_len(), 3);
+        assert!(machine.step_back());
+        assert!(machine.step_back());
+        assert_eq!((machine.pc(), machine.get_reg(Register::A), machine.steps()), (1, 0, 1));
+        assert!(machine.step_back());
+        assert_eq!(machine.pc(), 0);
+        assert_eq!(machine.code()[1], Instruction::Inc(Register::A));
+        assert!(!machine.step_back());
+    }
+
+    #[test]
+    fn step_back_redecodes() {
+        let mut machine = machine("inc a\ntgl -1");
+        machine.enable_history(10);
+        machine.run();
+        assert_eq!(machine.code()[0], Instruction::Dec(Register::A));
+        assert!(machine.step_back());
+        assert!(machine.step_back());
+        machine.execute();
+        assert_eq!(machine.get_reg(Register::A), 1);
+    }
+
+    #[test]
+    fn restore_redecodes() {
+        let snapshot = machine("dec a").snapshot();
+        let mut machine = machine("inc a");
+        machine.restore(&snapshot);
+        machine.run();
+        assert_eq!(machine.get_reg(Register::A), -1);
+    }
+
+    #[test]
+    fn step_back_is_deterministic() {
+        let input = include_str!("../../23/input.txt");
+        let mut machine = machine(input);
+        *machine.get_reg_mut(Register::A) = 7;
+        machine.enable_optimizer();
+        machine.enable_history(1000);
+        let mut states = Vec::new();
+        while machine.halt_reason().is_none() {
+            states.push(machine.snapshot());
+            machine.execute();
+        }
+        while machine.history_len() > 0 {
+            assert!(machine.step_back());
+            assert_eq!(machine.snapshot(), states.pop().unwrap());
+        }
+        assert_eq!(machine.history_len(), 0);
+    }
+
+    #[test]
+    fn history_depth() {
+        let mut machine = machine("inc a\ninc a\ninc a\ninc a");
+        machine.enable_history(2);
+        machine.run();
+        assert!(machine.step_back());
+        assert!(machine.step_back());
+        assert!(!machine.step_back());
+        assert_eq!(machine.get_reg(Register::A), 2);
+    }
+
+    #[test]
+    fn step_back_from_overflow() {
+        let mut machine = machine("inc a\ninc a");
+        *machine.get_reg_mut(Register::A) = i32::MAX - 1;
+        machine.enable_overflow_check();
+        machine.enable_history(10);
+        machine.run();
+        assert_eq!(machine.halt_reason(), Some(Halt::Overflow { pc: 1 }));
+        assert!(machine.step_back());
+        assert_eq!(machine.halt_reason(), None);
+        assert_eq!((machine.pc(), machine.get_reg(Register::A)), (1, i32::MAX));
+    }
+}
@@ -0,0 +1,451 @@
+//! Instructions defined by what they parse, do and toggle into.
+//!
+//! Every mnemonic is backed by an `Opcode`: it declares the operands the
+//! parser accepts, executes the instruction on a `Cpu` and says what `tgl`
+//! turns it into. Downstream code adds its own by implementing `Opcode` for a
+//! unit struct and registering it with `InstructionSet::with`, its
+//! instructions are then held in `Instruction::Extension`.
+//!
+//! The built-in instructions are opcodes as well, for parsing, `tgl` and
+//! `jumps`, but their execution is special-cased: `Machine` runs them in their
+//! decoded form and `SymbolicMachine` matches on their `Instruction` variants,
+//! so their opcodes keep the default `execute`. Both machines execute
+//! extensions through their opcode. `cfg` ends a block after any instruction
+//! which `jumps`, an extension jumps to an unknown target. `translate` only
+//! knows the built-in instructions.
+
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use instruction::{FromLocation, Instruction, Invalid, Register};
+
+/// What the parser accepts for an operand.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OperandKind {
+    Register,
+    /// A register or an integer.
+    Value,
+    /// Like `Value`, but the assembler also accepts a label, which is resolved
+    /// to the offset of the labelled instruction relative to this one.
+    Offset,
+}
+
+/// The machine an opcode executes on.
+///
+/// Operations are carried out with the register type of the machine, so they
+/// wrap or halt on overflow like the built-in instructions do.
+pub trait Cpu {
+    /// The value of a register or integer, saturated to `i64`.
+    fn value(&mut self, location: &FromLocation) -> i64;
+    fn is_zero(&mut self, location: &FromLocation) -> bool;
+    fn copy(&mut self, src: &FromLocation, dst: Register);
+    fn add(&mut self, dst: Register, value: &FromLocation);
+    fn sub(&mut self, dst: Register, value: &FromLocation);
+    fn mul(&mut self, dst: Register, value: &FromLocation);
+    /// Continues at the instruction `offset` away from this one instead of the next.
+    fn jump(&mut self, offset: &FromLocation);
+    /// Toggles the instruction `offset` away from this one.
+    fn toggle(&mut self, offset: &FromLocation);
+    fn output(&mut self, value: &FromLocation);
+}
+
+pub trait Opcode: Sync {
+    fn mnemonic(&self) -> &'static str;
+
+    fn operands(&self) -> &'static [OperandKind];
+
+    /// Executes the instruction, `operands` match the kinds of `operands()`.
+    ///
+    /// Only called for extensions, the default does nothing.
+    fn execute(&self, _operands: &[FromLocation], _cpu: &mut dyn Cpu) {
+    }
+
+    /// Whether the instruction may call `Cpu::jump`.
+    ///
+    /// The control-flow graph assumes an extension jumps unless it says
+    /// otherwise, since it can't tell where.
+    fn jumps(&self) -> bool {
+        true
+    }
+
+    /// The instruction `tgl` turns this one into, `None` leaves it as it is.
+    ///
+    /// By default it follows the rule of puzzle 23 for instructions other
+    /// than `inc` and `jnz`, see `toggle_by_arity`.
+    fn toggle(&self, operands: Vec<FromLocation>) -> Option<Instruction> {
+        toggle_by_arity(operands)
+    }
+
+    /// The built-in instruction with these operands.
+    ///
+    /// Only the built-in opcodes, which have their own `Instruction` variant,
+    /// return `Some`. Operands of the wrong kind give an `Invalid` instruction.
+    fn build(&self, _operands: &[FromLocation]) -> Option<Instruction> {
+        None
+    }
+}
+
+/// Toggles an instruction other than `inc` and `jnz`.
+///
+/// An instruction with one operand becomes `inc`, one with two becomes `jnz`,
+/// keeping the operands. Others are left as they are.
+pub fn toggle_by_arity(operands: Vec<FromLocation>) -> Option<Instruction> {
+    match operands.len() {
+        1 => Some(Instruction::new(&Inc, operands)),
+        2 => Some(Instruction::new(&Jnz, operands)),
+        _ => None,
+    }
+}
+
+/// An instruction of an opcode registered by downstream code.
+#[derive(Clone)]
+pub struct Extension {
+    opcode: &'static dyn Opcode,
+    operands: Vec<FromLocation>,
+}
+
+impl Extension {
+    pub fn new(opcode: &'static dyn Opcode, operands: Vec<FromLocation>) -> Extension {
+        Extension { opcode, operands }
+    }
+
+    pub fn opcode(&self) -> &'static dyn Opcode {
+        self.opcode
+    }
+
+    pub fn operands(&self) -> &[FromLocation] {
+        &self.operands
+    }
+}
+
+/// Opcodes are compared by their mnemonic.
+impl PartialEq for Extension {
+    fn eq(&self, other: &Extension) -> bool {
+        self.opcode.mnemonic() == other.opcode.mnemonic() && self.operands == other.operands
+    }
+}
+
+impl Eq for Extension {}
+
+impl Hash for Extension {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.opcode.mnemonic().hash(state);
+        self.operands.hash(state);
+    }
+}
+
+impl fmt::Debug for Extension {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Extension")
+            .field("mnemonic", &self.opcode.mnemonic())
+            .field("operands", &self.operands)
+            .finish()
+    }
+}
+
+pub struct Cpy;
+pub struct Inc;
+pub struct Dec;
+pub struct Jnz;
+pub struct Tgl;
+pub struct Out;
+
+/// The built-in opcodes.
+pub static BUILTIN: [&dyn Opcode; 6] = [&Cpy, &Inc, &Dec, &Jnz, &Tgl, &Out];
+
+const REGISTER: &[OperandKind] = &[OperandKind::Register];
+const VALUE: &[OperandKind] = &[OperandKind::Value];
+
+impl Opcode for Cpy {
+    fn mnemonic(&self) -> &'static str {
+        "cpy"
+    }
+
+    fn operands(&self) -> &'static [OperandKind] {
+        &[OperandKind::Value, OperandKind::Register]
+    }
+
+    fn jumps(&self) -> bool {
+        false
+    }
+
+    fn build(&self, operands: &[FromLocation]) -> Option<Instruction> {
+        match *operands {
+            [ref src, FromLocation::Reg(dst)] => Some(Instruction::Cpy(src.clone(), dst)),
+            [ref src, FromLocation::Int(dst)] => Some(Instruction::Invalid(Invalid::Cpy(src.clone(), dst))),
+            _ => None,
+        }
+    }
+}
+
+impl Opcode for Inc {
+    fn mnemonic(&self) -> &'static str {
+        "inc"
+    }
+
+    fn operands(&self) -> &'static [OperandKind] {
+        REGISTER
+    }
+
+    fn jumps(&self) -> bool {
+        false
+    }
+
+    fn toggle(&self, operands: Vec<FromLocation>) -> Option<Instruction> {
+        Some(Instruction::new(&Dec, operands))
+    }
+
+    fn build(&self, operands: &[FromLocation]) -> Option<Instruction> {
+        match *operands {
+            [FromLocation::Reg(reg)] => Some(Instruction::Inc(reg)),
+            [FromLocation::Int(value)] => Some(Instruction::Invalid(Invalid::Inc(value))),
+            _ => None,
+        }
+    }
+}
+
+impl Opcode for Dec {
+    fn mnemonic(&self) -> &'static str {
+        "dec"
+    }
+
+    fn operands(&self) -> &'static [OperandKind] {
+        REGISTER
+    }
+
+    fn jumps(&self) -> bool {
+        false
+    }
+
+    fn build(&self, operands: &[FromLocation]) -> Option<Instruction> {
+        match *operands {
+            [FromLocation::Reg(reg)] => Some(Instruction::Dec(reg)),
+            [FromLocation::Int(value)] => Some(Instruction::Invalid(Invalid::Dec(value))),
+            _ => None,
+        }
+    }
+}
+
+impl Opcode for Jnz {
+    fn mnemonic(&self) -> &'static str {
+        "jnz"
+    }
+
+    fn operands(&self) -> &'static [OperandKind] {
+        &[OperandKind::Value, OperandKind::Offset]
+    }
+
+    fn toggle(&self, operands: Vec<FromLocation>) -> Option<Instruction> {
+        Some(Instruction::new(&Cpy, operands))
+    }
+
+    fn build(&self, operands: &[FromLocation]) -> Option<Instruction> {
+        match *operands {
+            [ref value, ref offset] => Some(Instruction::Jnz(value.clone(), offset.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl Opcode for Tgl {
+    fn mnemonic(&self) -> &'static str {
+        "tgl"
+    }
+
+    fn operands(&self) -> &'static [OperandKind] {
+        VALUE
+    }
+
+    fn jumps(&self) -> bool {
+        false
+    }
+
+    fn build(&self, operands: &[FromLocation]) -> Option<Instruction> {
+        match *operands {
+            [ref offset] => Some(Instruction::Tgl(offset.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl Opcode for Out {
+    fn mnemonic(&self) -> &'static str {
+        "out"
+    }
+
+    fn operands(&self) -> &'static [OperandKind] {
+        VALUE
+    }
+
+    fn jumps(&self) -> bool {
+        false
+    }
+
+    fn build(&self, operands: &[FromLocation]) -> Option<Instruction> {
+        match *operands {
+            [ref value] => Some(Instruction::Out(value.clone())),
+            _ => None,
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cfg::{Block, Cfg, Edge, EdgeKind, Target};
+    use instruction::InstructionSet;
+    use machine::{Halt, Machine, MachineConfig, Outcome};
+    use snapshot::Snapshot;
+    use symbolic::SymbolicMachine;
+
+    /// `mul x y` multiplies register x by y.
+    struct Mul;
+
+    impl Opcode for Mul {
+        fn mnemonic(&self) -> &'static str {
+            "mul"
+        }
+
+        fn operands(&self) -> &'static [OperandKind] {
+            &[OperandKind::Register, OperandKind::Value]
+        }
+
+        fn execute(&self, operands: &[FromLocation], cpu: &mut dyn Cpu) {
+            if let [FromLocation::Reg(dst), ref value] = *operands {
+                cpu.mul(dst, value);
+            }
+        }
+
+        fn jumps(&self) -> bool {
+            false
+        }
+    }
+
+    /// `jgz x y` jumps by y if x is greater than zero.
+    struct Jgz;
+
+    impl Opcode for Jgz {
+        fn mnemonic(&self) -> &'static str {
+            "jgz"
+        }
+
+        fn operands(&self) -> &'static [OperandKind] {
+            &[OperandKind::Value, OperandKind::Offset]
+        }
+
+        fn execute(&self, operands: &[FromLocation], cpu: &mut dyn Cpu) {
+            if let [ref value, ref offset] = *operands {
+                if cpu.value(value) > 0 {
+                    cpu.jump(offset);
+                }
+            }
+        }
+    }
+
+    fn instruction_set() -> InstructionSet {
+        InstructionSet::full().with(&Mul).with(&Jgz)
+    }
+
+    // a = 2^b, counting b down to zero
+    const POWER: &str = "\
+cpy 1 a
+loop:
+    jgz b body
+    jnz 1 end
+body:
+    mul a 2
+    dec b
+    jnz 1 loop
+end:";
+
+    #[test]
+    fn parse_extension() {
+        let instruction = instruction_set().parse("mul c -3").unwrap();
+        assert_eq!(instruction, Instruction::Extension(
+            Extension::new(&Mul, vec![FromLocation::Reg(Register::C), FromLocation::Int(-3)])));
+        assert_eq!(instruction.to_string(), "mul c -3");
+        assert_eq!(instruction_set().parse(&instruction.to_string()), Ok(instruction));
+        assert!(instruction_set().parse("mul 3 c").is_err());
+        assert!(InstructionSet::full().parse("mul c 3").is_err());
+    }
+
+    #[test]
+    fn builtin_round_trip() {
+        let code = InstructionSet::full().parse_program("cpy 1 a\ninc b\ndec c\njnz d -1\ntgl a\nout 3\ninvalid cpy 1 2").unwrap();
+        for instruction in code {
+            assert_eq!(Instruction::new(instruction.opcode(), instruction.operands()), instruction);
+            assert!(BUILTIN.iter().any(|o| o.mnemonic() == instruction.opcode().mnemonic()));
+        }
+        let jumping: Vec<_> = BUILTIN.iter().filter(|o| o.jumps()).map(|o| o.mnemonic()).collect();
+        assert_eq!(jumping, ["jnz"]);
+    }
+
+    #[test]
+    fn toggle_extension() {
+        let mul = instruction_set().parse("mul a b").unwrap();
+        assert_eq!(mul.toggle(), Instruction::Jnz(FromLocation::Reg(Register::A), FromLocation::Reg(Register::B)));
+        assert_eq!(toggle_by_arity(vec![FromLocation::Reg(Register::C)]), Some(Instruction::Inc(Register::C)));
+        assert_eq!(toggle_by_arity(vec![FromLocation::Int(1)]), Some(Instruction::Invalid(Invalid::Inc(1))));
+        assert_eq!(toggle_by_arity(Vec::new()), None);
+    }
+
+    #[test]
+    fn execute_extension() {
+        let code = instruction_set().assemble(POWER).unwrap();
+        let mut machine = Machine::new(code.clone());
+        *machine.get_reg_mut(Register::B) = 10;
+        machine.run();
+        assert_eq!(machine.get_reg(Register::A), 1024);
+
+        let mut symbolic = SymbolicMachine::new(code).symbolic(Register::B, 3);
+        assert!(symbolic.run(1000));
+        assert_eq!(symbolic.get_reg(Register::A).to_string(), "8");
+        assert_eq!(symbolic.assumptions().len(), 4);
+    }
+
+    #[test]
+    fn extension_jump_out_of_range() {
+        let code = instruction_set().parse_program("jgz 1 a\ninc b").unwrap();
+        let config = MachineConfig::default().reg(Register::A, i64::MIN);
+        let mut machine = config.build(code.clone());
+        assert_eq!(config.run(&mut machine), Outcome::Halted(Halt::JumpOutOfRange { from: 0, target: i64::MIN }));
+
+        let mut symbolic = SymbolicMachine::new(code).constant(Register::A, i64::MIN);
+        assert!(symbolic.run(10));
+    }
+
+    #[test]
+    fn extension_control_flow() {
+        let code = instruction_set().assemble(POWER).unwrap();
+        let cfg = Cfg::build(&code);
+        assert_eq!(cfg.blocks[1..3], [Block { start: 1, end: 2 }, Block { start: 2, end: 3 }]);
+        let edges: Vec<_> = cfg.edges.iter().filter(|e| e.from == 1).cloned().collect();
+        assert_eq!(edges, [
+            Edge { from: 1, to: Target::Unknown, kind: EdgeKind::Extension },
+            Edge { from: 1, to: Target::Block(2), kind: EdgeKind::Extension },
+        ]);
+        // `mul` doesn't jump, so it doesn't end the body
+        assert_eq!(cfg.block_of(5), cfg.block_of(3));
+    }
+
+    #[test]
+    fn snapshot_extension() {
+        let mut machine = Machine::new(instruction_set().assemble(POWER).unwrap());
+        machine.execute();
+        let snapshot = machine.snapshot();
+        let text = snapshot.to_string();
+        assert_eq!(Snapshot::parse_with(&text, &instruction_set()), Ok(snapshot));
+        assert!(text.parse::<Snapshot>().is_err());
+    }
+
+    #[test]
+    fn toggled_extension() {
+        let code = instruction_set().parse_program("cpy 2 a\ntgl a\ncpy 5 b\nmul b a").unwrap();
+        let mut machine = Machine::new(code);
+        machine.run();
+        assert_eq!(machine.code()[3], Instruction::Jnz(FromLocation::Reg(Register::B), FromLocation::Reg(Register::A)));
+        assert_eq!(machine.get_reg(Register::B), 5);
+    }
+}
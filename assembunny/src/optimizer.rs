@@ -0,0 +1,188 @@
+//! Peephole optimizer for the add and multiply loops assembunny programs use
+//! in place of arithmetic.
+//!
+//! A fused op is stored at the pc of the first instruction of the loop it
+//! replaces and covers the following slots as well. The original instructions
+//! stay in place, so a jump into the middle of a loop or a fused op whose
+//! preconditions don't hold simply falls back to the instructions.
+
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+use instruction::{FromLocation, Instruction, Register};
+
+/// The longest sequence of instructions a single op replaces.
+const MAX_SLOTS: usize = 6;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// `inc dst / dec src / jnz src -2` (or with `inc` and `dec` swapped)
+    ///
+    /// Computes `dst += src; src = 0`.
+    Add { dst: Register, src: Register },
+    /// `cpy factor inner / inc dst / dec inner / jnz inner -2 / dec outer / jnz outer -5`
+    ///
+    /// Computes `dst += factor * outer; inner = 0; outer = 0`.
+    Mul { dst: Register, factor: FromLocation, inner: Register, outer: Register },
+}
+
+impl Op {
+    /// Number of instructions the op replaces.
+    pub fn slots(&self) -> usize {
+        match *self {
+            Op::Add { .. } => 3,
+            Op::Mul { .. } => 6,
+        }
+    }
+}
+
+/// Formats the op like an instruction with its operands in declaration
+/// order, prefixed with `fused`, as in `fused mul a b c d`.
+impl Display for Op {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Op::Add { dst, src } => write!(f, "fused add {} {}", dst, src),
+            Op::Mul { dst, ref factor, inner, outer } => {
+                write!(f, "fused mul {} {} {} {}", dst, factor, inner, outer)
+            }
+        }
+    }
+}
+
+/// Finds every add and multiply loop in `code`.
+///
+/// The result has one entry per instruction, holding the op starting there.
+pub fn optimize(code: &[Instruction]) -> Vec<Option<Op>> {
+    (0..code.len()).map(|pc| match_at(code, pc)).collect()
+}
+
+/// Re-derives all ops which cover `position` after `code[position]` changed.
+///
+/// Returns the range of pcs whose ops were re-derived.
+pub fn reoptimize(code: &[Instruction], ops: &mut [Option<Op>], position: usize) -> Range<usize> {
+    let first = position.saturating_sub(MAX_SLOTS - 1);
+    let last = (position + 1).min(code.len());
+    for (pc, op) in ops.iter_mut().enumerate().take(last).skip(first) {
+        *op = match_at(code, pc);
+    }
+    first..last
+}
+
+fn match_at(code: &[Instruction], pc: usize) -> Option<Op> {
+    match_mul(&code[pc..]).or_else(|| match_add(&code[pc..]))
+}
+
+fn match_add(code: &[Instruction]) -> Option<Op> {
+    if code.len() < 3 {
+        return None;
+    }
+    let (dst, src) = match (&code[0], &code[1]) {
+        (&Instruction::Inc(dst), &Instruction::Dec(src)) |
+        (&Instruction::Dec(src), &Instruction::Inc(dst)) => (dst, src),
+        _ => return None,
+    };
+    if dst != src && is_jnz_back(&code[2], src, 2) {
+        Some(Op::Add { dst, src })
+    } else {
+        None
+    }
+}
+
+fn match_mul(code: &[Instruction]) -> Option<Op> {
+    if code.len() < 6 {
+        return None;
+    }
+    let (factor, inner) = match code[0] {
+        Instruction::Cpy(ref factor, inner) => (factor.clone(), inner),
+        _ => return None,
+    };
+    let dst = match match_add(&code[1..]) {
+        Some(Op::Add { dst, src }) if src == inner => dst,
+        _ => return None,
+    };
+    let outer = match code[4] {
+        Instruction::Dec(outer) => outer,
+        _ => return None,
+    };
+    if !is_jnz_back(&code[5], outer, 5) || outer == dst || outer == inner {
+        return None;
+    }
+    if let FromLocation::Reg(reg) = factor {
+        if reg == dst || reg == inner || reg == outer {
+            return None;
+        }
+    }
+    Some(Op::Mul { dst, factor, inner, outer })
+}
+
+fn is_jnz_back(instruction: &Instruction, reg: Register, distance: i32) -> bool {
+    match *instruction {
+        Instruction::Jnz(FromLocation::Reg(r), FromLocation::Int(offset)) => {
+            r == reg && offset == -distance
+        }
+        _ => false,
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use instruction::InstructionSet;
+
+    fn parse(input: &str) -> Vec<Instruction> {
+        InstructionSet::base().with_tgl().parse_program(input).unwrap()
+    }
+
+    #[test]
+    fn finds_add() {
+        let code = parse("cpy 3 b\ninc a\ndec b\njnz b -2");
+        assert_eq!(optimize(&code), vec![
+            None,
+            Some(Op::Add { dst: Register::A, src: Register::B }),
+            None,
+            None,
+        ]);
+    }
+
+    #[test]
+    fn finds_add_with_dec_first() {
+        let code = parse("dec d\ninc c\njnz d -2");
+        assert_eq!(optimize(&code)[0], Some(Op::Add { dst: Register::C, src: Register::D }));
+    }
+
+    #[test]
+    fn ignores_add_with_wrong_offset() {
+        let code = parse("inc a\ndec b\njnz b -3");
+        assert_eq!(optimize(&code)[0], None);
+    }
+
+    #[test]
+    fn finds_mul() {
+        let code = parse("cpy b c\ninc a\ndec c\njnz c -2\ndec d\njnz d -5");
+        let ops = optimize(&code);
+        assert_eq!(ops[0], Some(Op::Mul {
+            dst: Register::A,
+            factor: FromLocation::Reg(Register::B),
+            inner: Register::C,
+            outer: Register::D,
+        }));
+        assert_eq!(ops[1], Some(Op::Add { dst: Register::A, src: Register::C }));
+    }
+
+    #[test]
+    fn ignores_mul_with_aliased_factor() {
+        let code = parse("cpy d c\ninc a\ndec c\njnz c -2\ndec d\njnz d -5");
+        assert_eq!(optimize(&code)[0], None);
+    }
+
+    #[test]
+    fn reoptimize_drops_broken_op() {
+        let mut code = parse("inc a\ndec b\njnz b -2");
+        let mut ops = optimize(&code);
+        code[2] = code[2].clone().toggle();
+        assert_eq!(reoptimize(&code, &mut ops, 2), 0..3);
+        assert_eq!(ops, vec![None, None, None]);
+    }
+}
@@ -0,0 +1,48 @@
+//! Where the values of `out` go.
+
+/// Receives every value a program outputs.
+pub trait Sink<W> {
+    fn emit(&mut self, value: W);
+}
+
+/// Collects the values.
+impl<W> Sink<W> for Vec<W> {
+    fn emit(&mut self, value: W) {
+        self.push(value);
+    }
+}
+
+/// Calls the closure with every value.
+pub struct Callback<F>(pub F);
+
+impl<W, F: FnMut(W)> Sink<W> for Callback<F> {
+    fn emit(&mut self, value: W) {
+        (self.0)(value)
+    }
+}
+
+/// Drops the values, used by `Machine::execute`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Discard;
+
+impl<W> Sink<W> for Discard {
+    fn emit(&mut self, _: W) {
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn callback() {
+        let mut sum = 0;
+        {
+            let mut sink = Callback(|value| sum += value);
+            sink.emit(3);
+            sink.emit(4);
+        }
+        assert_eq!(sum, 7);
+    }
+}
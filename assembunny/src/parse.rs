@@ -0,0 +1,489 @@
+//! Parser for assembunny source which reports the position of every error.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+
+use instruction::{FromLocation, Instruction, InstructionSet, Register};
+use opcode::{self, OperandKind};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    MissingMnemonic,
+    UnknownMnemonic,
+    /// The mnemonic exists but isn't part of the instruction set in use.
+    UnsupportedInstruction,
+    MissingOperand,
+    UnexpectedOperand,
+    BadRegister,
+    BadInteger,
+    /// `invalid` in front of operands which make a valid instruction.
+    NotInvalid,
+    /// A label definition which isn't a valid name or clashes with a register.
+    BadLabel,
+    DuplicateLabel,
+    UndefinedLabel,
+}
+
+impl Display for ParseErrorKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let description = match *self {
+            ParseErrorKind::MissingMnemonic => "missing mnemonic",
+            ParseErrorKind::UnknownMnemonic => "unknown mnemonic",
+            ParseErrorKind::UnsupportedInstruction => "instruction not supported",
+            ParseErrorKind::MissingOperand => "missing operand",
+            ParseErrorKind::UnexpectedOperand => "unexpected operand",
+            ParseErrorKind::BadRegister => "bad register",
+            ParseErrorKind::BadInteger => "bad integer literal",
+            ParseErrorKind::NotInvalid => "valid instruction marked invalid",
+            ParseErrorKind::BadLabel => "bad label",
+            ParseErrorKind::DuplicateLabel => "duplicate label",
+            ParseErrorKind::UndefinedLabel => "undefined label",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+/// An error in the source together with the token which caused it.
+///
+/// `line` and `column` are 1-based. For a missing mnemonic or operand the
+/// token is empty and the column points just past the end of the line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub line: usize,
+    pub column: usize,
+    pub token: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.kind)?;
+        if !self.token.is_empty() {
+            write!(f, " `{}`", self.token)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ParseError {}
+
+/// All errors of a program, as returned by `InstructionSet::assemble`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseErrors(pub Vec<ParseError>);
+
+impl Display for ParseErrors {
+    /// Prints every error on its own line.
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let messages: Vec<_> = self.0.iter().map(|e| e.to_string()).collect();
+        write!(f, "{}", messages.join("\n"))
+    }
+}
+
+impl Error for ParseErrors {}
+
+/// Why a program couldn't be loaded from a file.
+#[derive(Debug)]
+pub enum LoadError {
+    Io { path: String, error: io::Error },
+    Parse { path: String, errors: Vec<ParseError> },
+}
+
+impl Display for LoadError {
+    /// Prints every parse error on its own line, prefixed with the path.
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Io { ref path, ref error } => write!(f, "Could not read {}: {}", path, error),
+            LoadError::Parse { ref path, ref errors } => {
+                let messages: Vec<_> = errors.iter().map(|e| format!("{}: {}", path, e)).collect();
+                write!(f, "{}", messages.join("\n"))
+            }
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+/// Reads and assembles the program at `path`, see `InstructionSet::load`.
+pub fn load_program(path: &str) -> Result<Vec<Instruction>, LoadError> {
+    InstructionSet::full().load(path)
+}
+
+/// Reads `path` and assembles it with `instruction_set`.
+pub fn load_program_with(instruction_set: &InstructionSet, path: &str) -> Result<Vec<Instruction>, LoadError> {
+    let input = fs::read_to_string(path)
+        .map_err(|error| LoadError::Io { path: path.to_string(), error })?;
+    assemble_program(instruction_set, &input)
+        .map_err(|errors| LoadError::Parse { path: path.to_string(), errors })
+}
+
+/// Parses a whole program using every instruction this crate knows.
+pub fn parse_program(input: &str) -> Result<Vec<Instruction>, Vec<ParseError>> {
+    InstructionSet::full().parse_program(input)
+}
+
+/// Assembles a whole program using every instruction this crate knows.
+pub fn assemble(input: &str) -> Result<Vec<Instruction>, Vec<ParseError>> {
+    InstructionSet::full().assemble(input)
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Token<'a> {
+    text: &'a str,
+    column: usize,
+}
+
+struct LineParser<'a> {
+    tokens: ::std::iter::Peekable<::std::vec::IntoIter<Token<'a>>>,
+    line: usize,
+    end_column: usize,
+}
+
+impl<'a> LineParser<'a> {
+    fn new(line: usize, source: &'a str) -> LineParser<'a> {
+        let mut tokens = Vec::new();
+        let mut start = None;
+        let mut column = 0;
+        for (index, (offset, c)) in source.char_indices().enumerate() {
+            column = index + 1;
+            match (c.is_whitespace(), start) {
+                (false, None) => start = Some((offset, column)),
+                (true, Some((first, first_column))) => {
+                    tokens.push(Token { text: &source[first..offset], column: first_column });
+                    start = None;
+                }
+                _ => (),
+            }
+        }
+        if let Some((first, first_column)) = start {
+            tokens.push(Token { text: &source[first..], column: first_column });
+        }
+        LineParser { tokens: tokens.into_iter().peekable(), line, end_column: column + 1 }
+    }
+
+    fn error(&self, kind: ParseErrorKind, token: Token) -> ParseError {
+        ParseError { kind, line: self.line, column: token.column, token: token.text.into() }
+    }
+
+    fn missing(&self, kind: ParseErrorKind) -> ParseError {
+        self.error(kind, Token { text: "", column: self.end_column })
+    }
+
+    fn mnemonic(&mut self) -> Result<Token<'a>, ParseError> {
+        self.tokens.next().ok_or_else(|| self.missing(ParseErrorKind::MissingMnemonic))
+    }
+
+    fn operand(&mut self) -> Result<Token<'a>, ParseError> {
+        self.tokens.next().ok_or_else(|| self.missing(ParseErrorKind::MissingOperand))
+    }
+
+    fn register(&mut self) -> Result<Register, ParseError> {
+        let token = self.operand()?;
+        register(token.text).ok_or_else(|| self.error(ParseErrorKind::BadRegister, token))
+    }
+
+    fn location(&mut self) -> Result<FromLocation, ParseError> {
+        let token = self.operand()?;
+        if let Some(reg) = register(token.text) {
+            return Ok(FromLocation::Reg(reg));
+        }
+        match token.text.parse() {
+            Ok(value) => Ok(FromLocation::Int(value)),
+            Err(_) => {
+                // "e" was most likely meant to be a register, "4x" a number
+                let kind = if token.text.starts_with(|c: char| c.is_alphabetic()) {
+                    ParseErrorKind::BadRegister
+                } else {
+                    ParseErrorKind::BadInteger
+                };
+                Err(self.error(kind, token))
+            }
+        }
+    }
+
+    /// An offset, which may name a label instead if `labels` is set.
+    fn offset(&mut self, labels: bool) -> Result<(FromLocation, Option<Token<'a>>), ParseError> {
+        match self.tokens.peek() {
+            Some(&token) if labels && is_label(token.text) => {
+                self.tokens.next();
+                // the offset is filled in once all labels are known
+                Ok((FromLocation::Int(0), Some(token)))
+            }
+            _ => Ok((self.location()?, None)),
+        }
+    }
+
+    /// Parses an instruction, returning the operands which name a label.
+    fn instruction(&mut self, instruction_set: &InstructionSet, labels: bool)
+        -> Result<(Instruction, Vec<(usize, Token<'a>)>), ParseError>
+    {
+        let first = self.mnemonic()?;
+        // only produced by tgl, accepted so that toggled code can be written out and read back
+        let invalid = first.text == "invalid";
+        let mnemonic = if invalid { self.mnemonic()? } else { first };
+        let (opcode, supported) = match instruction_set.opcode(mnemonic.text) {
+            Some(opcode) => (opcode, true),
+            None => match opcode::BUILTIN.iter().find(|o| o.mnemonic() == mnemonic.text) {
+                Some(&opcode) => (opcode, false),
+                None => return Err(self.error(ParseErrorKind::UnknownMnemonic, mnemonic)),
+            },
+        };
+        let mut operands = Vec::new();
+        let mut targets = Vec::new();
+        for (index, kind) in opcode.operands().iter().enumerate() {
+            let operand = match *kind {
+                _ if invalid => self.location()?,
+                OperandKind::Register => FromLocation::Reg(self.register()?),
+                OperandKind::Value => self.location()?,
+                OperandKind::Offset => {
+                    let (offset, label) = self.offset(labels)?;
+                    targets.extend(label.map(|label| (index, label)));
+                    offset
+                }
+            };
+            operands.push(operand);
+        }
+        self.finish()?;
+        let instruction = Instruction::new(opcode, operands);
+        if invalid && !instruction.is_invalid() {
+            return Err(self.error(ParseErrorKind::NotInvalid, first));
+        }
+        if supported && instruction_set.supports(&instruction) {
+            Ok((instruction, targets))
+        } else {
+            Err(self.error(ParseErrorKind::UnsupportedInstruction, first))
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), ParseError> {
+        match self.tokens.next() {
+            Some(token) => Err(self.error(ParseErrorKind::UnexpectedOperand, token)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn register(s: &str) -> Option<Register> {
+    match s {
+        "a" => Some(Register::A),
+        "b" => Some(Register::B),
+        "c" => Some(Register::C),
+        "d" => Some(Register::D),
+        _ => None,
+    }
+}
+
+fn is_label(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_alphabetic() || c == '_') &&
+        s.chars().all(|c| c.is_alphanumeric() || c == '_') &&
+        register(s).is_none()
+}
+
+/// Parses a single line of source, `line` is only used for error reporting.
+pub fn parse_line(instruction_set: &InstructionSet, line: usize, source: &str)
+    -> Result<Instruction, ParseError>
+{
+    LineParser::new(line, source).instruction(instruction_set, false).map(|(instruction, _)| instruction)
+}
+
+/// Parses source which may contain labels, comments and blank lines.
+///
+/// A line holds any number of `label:` definitions followed by an optional
+/// instruction. The offset of a `jnz` can name a label, which is resolved to
+/// the relative offset of the instruction following the label. Comments start
+/// with `#` or `;` and run to the end of the line.
+pub fn assemble_program(instruction_set: &InstructionSet, input: &str)
+    -> Result<Vec<Instruction>, Vec<ParseError>>
+{
+    let mut code = Vec::new();
+    let mut labels = HashMap::new();
+    let mut jumps = Vec::new();
+    let mut errors = Vec::new();
+    for (k, source) in input.lines().enumerate() {
+        let source = source.find(['#', ';']).map_or(source, |end| &source[..end]);
+        let mut parser = LineParser::new(k + 1, source);
+        while let Some(&token) = parser.tokens.peek() {
+            if !token.text.ends_with(':') {
+                break;
+            }
+            parser.tokens.next();
+            let name = &token.text[..token.text.len() - 1];
+            if !is_label(name) {
+                errors.push(parser.error(ParseErrorKind::BadLabel, token));
+            } else if labels.insert(name, code.len()).is_some() {
+                errors.push(parser.error(ParseErrorKind::DuplicateLabel, token));
+            }
+        }
+        if parser.tokens.peek().is_none() {
+            continue;
+        }
+        match parser.instruction(instruction_set, true) {
+            Ok((instruction, targets)) => {
+                for (index, target) in targets {
+                    jumps.push((code.len(), index, parser.error(ParseErrorKind::UndefinedLabel, target)));
+                }
+                code.push(instruction);
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+    for (position, index, undefined) in jumps {
+        match labels.get(undefined.token.as_str()) {
+            Some(&target) => {
+                let mut operands = code[position].operands();
+                operands[index] = FromLocation::Int(target as i32 - position as i32);
+                code[position] = Instruction::new(code[position].opcode(), operands);
+            }
+            None => errors.push(undefined),
+        }
+    }
+    if errors.is_empty() {
+        Ok(code)
+    } else {
+        errors.sort_by_key(|e| (e.line, e.column));
+        Err(errors)
+    }
+}
+
+pub fn parse_register(source: &str) -> Result<Register, ParseError> {
+    let mut parser = LineParser::new(1, source);
+    let reg = parser.register()?;
+    parser.finish()?;
+    Ok(reg)
+}
+
+pub fn parse_location(source: &str) -> Result<FromLocation, ParseError> {
+    let mut parser = LineParser::new(1, source);
+    let location = parser.location()?;
+    parser.finish()?;
+    Ok(location)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn error(kind: ParseErrorKind, line: usize, column: usize, token: &str) -> ParseError {
+        ParseError { kind, line, column, token: token.into() }
+    }
+
+    #[test]
+    fn bad_integer() {
+        let result: Result<Instruction, _> = "cpy 4x a".parse();
+        assert_eq!(result, Err(error(ParseErrorKind::BadInteger, 1, 5, "4x")));
+    }
+
+    #[test]
+    fn bad_register() {
+        let result: Result<Instruction, _> = "cpy 4 e".parse();
+        assert_eq!(result, Err(error(ParseErrorKind::BadRegister, 1, 7, "e")));
+        let result: Result<Instruction, _> = "jnz x 2".parse();
+        assert_eq!(result, Err(error(ParseErrorKind::BadRegister, 1, 5, "x")));
+    }
+
+    #[test]
+    fn unknown_mnemonic() {
+        let result: Result<Instruction, _> = "  mul a b".parse();
+        assert_eq!(result, Err(error(ParseErrorKind::UnknownMnemonic, 1, 3, "mul")));
+    }
+
+    #[test]
+    fn missing_operand() {
+        let result: Result<Instruction, _> = "cpy 1".parse();
+        assert_eq!(result, Err(error(ParseErrorKind::MissingOperand, 1, 6, "")));
+    }
+
+    #[test]
+    fn unexpected_operand() {
+        let result: Result<Instruction, _> = "inc a b".parse();
+        assert_eq!(result, Err(error(ParseErrorKind::UnexpectedOperand, 1, 7, "b")));
+    }
+
+    #[test]
+    fn unsupported_instruction() {
+        let result = InstructionSet::base().parse("tgl a");
+        assert_eq!(result, Err(error(ParseErrorKind::UnsupportedInstruction, 1, 1, "tgl")));
+    }
+
+    #[test]
+    fn reports_every_bad_line() {
+        let input = "cpy 1 a\ncpy 4x a\ninc a\n\njnz a";
+        assert_eq!(parse_program(input), Err(vec![
+            error(ParseErrorKind::BadInteger, 2, 5, "4x"),
+            error(ParseErrorKind::MissingMnemonic, 4, 1, ""),
+            error(ParseErrorKind::MissingOperand, 5, 6, ""),
+        ]));
+    }
+
+    #[test]
+    fn assemble_labels() {
+        let input = "
+            cpy 2 b      # counter
+        loop: inc a
+            dec b
+            jnz b loop   ; back to the start
+            jnz 1 end
+            inc c
+        end:";
+        assert_eq!(assemble(input), parse_program("cpy 2 b\ninc a\ndec b\njnz b -2\njnz 1 2\ninc c"));
+    }
+
+    #[test]
+    fn assemble_forward_and_stacked_labels() {
+        let input = "jnz a skip\nfirst: second: inc a\nskip: jnz 1 first\njnz 1 second";
+        assert_eq!(assemble(input), parse_program("jnz a 2\ninc a\njnz 1 -1\njnz 1 -2"));
+    }
+
+    #[test]
+    fn assemble_errors() {
+        let input = "a: inc a\nloop: inc b\nloop: jnz a nowhere\njnz 1 4x\n2nd: dec a";
+        assert_eq!(assemble(input), Err(vec![
+            error(ParseErrorKind::BadLabel, 1, 1, "a:"),
+            error(ParseErrorKind::DuplicateLabel, 3, 1, "loop:"),
+            error(ParseErrorKind::UndefinedLabel, 3, 13, "nowhere"),
+            error(ParseErrorKind::BadInteger, 4, 7, "4x"),
+            error(ParseErrorKind::BadLabel, 5, 1, "2nd:"),
+        ]));
+    }
+
+    #[test]
+    fn labels_only_in_assembler() {
+        let result: Result<Instruction, _> = "jnz a loop".parse();
+        assert_eq!(result, Err(error(ParseErrorKind::BadRegister, 1, 7, "loop")));
+        let result: Result<Instruction, _> = "inc a # comment".parse();
+        assert_eq!(result, Err(error(ParseErrorKind::UnexpectedOperand, 1, 7, "#")));
+    }
+
+    #[test]
+    fn display() {
+        let e = error(ParseErrorKind::BadInteger, 2, 5, "4x");
+        assert_eq!(e.to_string(), "line 2, column 5: bad integer literal `4x`");
+        let errors = ParseErrors(vec![e, error(ParseErrorKind::MissingOperand, 3, 6, "")]);
+        assert_eq!(errors.to_string(), "line 2, column 5: bad integer literal `4x`\nline 3, column 6: missing operand");
+    }
+
+    #[test]
+    fn load() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../12/programs/sample.txt");
+        assert_eq!(load_program(path).unwrap(), assemble(include_str!("../../12/programs/sample.txt")).unwrap());
+        match load_program("no/such/program.txt") {
+            Err(ref e @ LoadError::Io { .. }) => assert!(e.to_string().starts_with("Could not read no/such/program.txt: ")),
+            result => panic!("Expected an io error, got {:?}", result),
+        }
+        let tgl = concat!(env!("CARGO_MANIFEST_DIR"), "/../23/input.txt");
+        assert!(InstructionSet::base().with_tgl().load(tgl).is_ok());
+        match InstructionSet::base().load(tgl) {
+            Err(LoadError::Parse { ref errors, .. }) => assert_eq!(errors[0].kind, ParseErrorKind::UnsupportedInstruction),
+            result => panic!("Expected a parse error, got {:?}", result),
+        }
+        let e = LoadError::Parse { path: "p".into(), errors: vec![
+            error(ParseErrorKind::BadInteger, 2, 5, "4x"),
+            error(ParseErrorKind::UndefinedLabel, 3, 7, "loop"),
+        ] };
+        assert_eq!(e.to_string(), "p: line 2, column 5: bad integer literal `4x`\np: line 3, column 7: undefined label `loop`");
+    }
+}
@@ -0,0 +1,181 @@
+//! Saved machine states, which can be written to disk and read back.
+//!
+//! The text format starts with a version line, followed by the state and the
+//! code, one instruction per line as `disasm::format` writes it:
+//!
+//! ```text
+//! assembunny snapshot 1
+//! registers 7 0 0 0
+//! pc 16
+//! steps 12345
+//! code
+//! cpy a b
+//! dec b
+//! ```
+
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use instruction::{Instruction, InstructionSet, Register};
+use word::Word;
+
+const HEADER: &str = "assembunny snapshot 1";
+
+/// The state of a machine, see `Machine::snapshot` and `Machine::restore`.
+///
+/// The fields can be changed before restoring, to fork a run with different
+/// register values.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot<W = i32> {
+    pub registers: [W; 4],
+    pub pc: i64,
+    /// The code including the changes made by `tgl`.
+    pub code: Vec<Instruction>,
+    pub steps: u64,
+}
+
+impl<W: Word> Snapshot<W> {
+    /// Reads a snapshot whose code uses the instructions of `instructions`.
+    ///
+    /// `str::parse` reads one with `InstructionSet::full()`.
+    pub fn parse_with(s: &str, instructions: &InstructionSet) -> Result<Snapshot<W>, String> {
+        let mut lines = s.lines();
+        if lines.next() != Some(HEADER) {
+            return Err("Not a snapshot".into());
+        }
+        let values: Vec<W> = field(lines.next(), "registers")?
+            .split_whitespace()
+            .map(|v| v.parse().map_err(|_| format!("Invalid register value: {}", v)))
+            .collect::<Result<_, _>>()?;
+        if values.len() != 4 {
+            return Err(format!("Expected 4 registers, got {}", values.len()));
+        }
+        let mut registers = [W::default(); 4];
+        registers.copy_from_slice(&values);
+        let pc = field(lines.next(), "pc")?;
+        let pc = pc.parse().map_err(|_| format!("Invalid pc: {}", pc))?;
+        let steps = field(lines.next(), "steps")?;
+        let steps = steps.parse().map_err(|_| format!("Invalid step count: {}", steps))?;
+        if lines.next() != Some("code") {
+            return Err("Missing code".into());
+        }
+        let code: Vec<&str> = lines.collect();
+        let code = instructions.parse_program(&code.join("\n")).map_err(|errors| {
+            let messages: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+            messages.join("\n")
+        })?;
+        Ok(Snapshot { registers, pc, code, steps })
+    }
+
+    pub fn with_reg(mut self, reg: Register, value: W) -> Snapshot<W> {
+        self.registers[reg as usize] = value;
+        self
+    }
+}
+
+impl<W: Word> Display for Snapshot<W> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        let registers: Vec<_> = self.registers.iter().map(|r| r.to_string()).collect();
+        writeln!(f, "registers {}", registers.join(" "))?;
+        writeln!(f, "pc {}", self.pc)?;
+        writeln!(f, "steps {}", self.steps)?;
+        writeln!(f, "code")?;
+        for instruction in &self.code {
+            writeln!(f, "{}", instruction)?;
+        }
+        Ok(())
+    }
+}
+
+/// The value of the line `<key> <value>`.
+fn field<'a>(line: Option<&'a str>, key: &str) -> Result<&'a str, String> {
+    let line = line.ok_or_else(|| format!("Missing {}", key))?;
+    let mut parts = line.splitn(2, ' ');
+    match (parts.next(), parts.next()) {
+        (Some(k), Some(value)) if k == key => Ok(value),
+        _ => Err(format!("Expected {}, got: {}", key, line)),
+    }
+}
+
+impl<W: Word> FromStr for Snapshot<W> {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Snapshot::parse_with(s, &InstructionSet::full())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use machine::Machine;
+    use parse::parse_program;
+
+    #[test]
+    fn text_round_trip() {
+        let mut machine = Machine::new(parse_program("tgl 1\njnz 1 2\ninc a").unwrap());
+        machine.execute();
+        let snapshot = machine.snapshot();
+        let text = snapshot.to_string();
+        assert_eq!(text, "\
+assembunny snapshot 1
+registers 0 0 0 0
+pc 1
+steps 1
+code
+tgl 1
+invalid cpy 1 2
+inc a
+");
+        assert_eq!(text.parse(), Ok(snapshot));
+    }
+
+    #[test]
+    fn resume_from_disk_format() {
+        let input = include_str!("../../23/input.txt");
+        let mut machine = Machine::new(parse_program(input).unwrap());
+        *machine.get_reg_mut(Register::A) = 7;
+        for _ in 0..5000 {
+            machine.execute();
+        }
+        let text = machine.snapshot().to_string();
+        machine.run();
+
+        let mut resumed = Machine::new(Vec::new());
+        resumed.restore(&text.parse().unwrap());
+        assert_eq!(resumed.steps(), 5000);
+        resumed.run();
+        assert_eq!(resumed.get_reg(Register::A), 11514);
+        assert_eq!(resumed.steps(), machine.steps());
+        assert_eq!(resumed.code(), machine.code());
+    }
+
+    #[test]
+    fn fork() {
+        let mut machine = Machine::new(parse_program("cpy 2 b\ninc a\ndec b\njnz b -2").unwrap());
+        machine.execute();
+        let snapshot = machine.snapshot();
+
+        let mut fork = Machine::new(Vec::new());
+        fork.enable_optimizer();
+        fork.restore(&snapshot.clone().with_reg(Register::B, 5));
+        fork.run();
+        assert_eq!(fork.get_reg(Register::A), 5);
+
+        machine.run();
+        assert_eq!(machine.get_reg(Register::A), 2);
+        machine.restore(&snapshot);
+        machine.run();
+        assert_eq!(machine.get_reg(Register::A), 2);
+    }
+
+    #[test]
+    fn rejects_bad_snapshots() {
+        assert!("registers 0 0 0 0".parse::<Snapshot>().is_err());
+        assert!("assembunny snapshot 1\nregisters 0 0 0\npc 0\nsteps 0\ncode\n".parse::<Snapshot>().is_err());
+        assert!("assembunny snapshot 1\nregisters 0 0 0 0\npc 0\nsteps 0\ncode\nfoo a\n".parse::<Snapshot>().is_err());
+    }
+}
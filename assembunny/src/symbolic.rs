@@ -0,0 +1,1160 @@
+//! Symbolic execution, deriving the registers as formulas of the initial registers.
+//!
+//! Every register holds a concrete value next to its formula. Branches, jump
+//! offsets and toggle targets are decided by the concrete values, so the
+//! formulas hold for every input taking the same path through the program.
+//! The assumptions made on the way are recorded. Add and multiply loops found
+//! by the optimizer are collapsed into a single sum or product.
+//!
+//! The other loops found by `Cfg::loops` run one iteration at a time, with
+//! formulas over the registers at the loop header. Identical iterations in a
+//! row which step an induction register by one are summarised once the
+//! iteration after them pins that register to a constant: the trip count then
+//! follows from the initial registers, so the formulas hold for every trip
+//! count instead of the one taken.
+
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::mem;
+use std::ops::{Add, Mul};
+
+use cfg::Cfg;
+use instruction::{FromLocation, Instruction, Register};
+use opcode::Cpu;
+use optimizer::{self, Op};
+
+/// A formula over the initial values of the registers.
+///
+/// Sums are kept in a linear form: like terms are combined, the terms with a
+/// negative coefficient come last and the constant comes at the end. Constant
+/// factors are folded and kept on the left of a product.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Expr {
+    Const(i64),
+    /// The initial value of a register.
+    Reg(Register),
+    Add(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    /// `low * (low + 1) * ... * high`, 1 if `high < low`.
+    Product(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn is_const(&self) -> bool {
+        matches!(*self, Expr::Const(_))
+    }
+
+    /// The product of the integers from `low` to `high`.
+    pub fn product(low: Expr, high: Expr) -> Expr {
+        if let (&Expr::Const(l), &Expr::Const(h)) = (&low, &high) {
+            if h < l {
+                return Expr::Const(1);
+            }
+            if l <= 0 && h >= 0 {
+                return Expr::Const(0);
+            }
+            if let Some(product) = (l..=h).try_fold(1i64, |p, k| p.checked_mul(k)) {
+                return Expr::Const(product);
+            }
+        }
+        match high.difference(&low) {
+            Some(0) => low,
+            Some(d) if d < 0 => Expr::Const(1),
+            // a factor of 1 doesn't count, so 1..n and 2..n are both n!
+            _ if low == Expr::Const(1) => Expr::Product(Box::new(Expr::Const(2)), Box::new(high)),
+            _ => Expr::Product(Box::new(low), Box::new(high)),
+        }
+    }
+
+    /// The value of the formula for the given initial registers, `None` if
+    /// it overflows an `i64`.
+    pub fn eval(&self, registers: &[i64; 4]) -> Option<i64> {
+        match *self {
+            Expr::Const(value) => Some(value),
+            Expr::Reg(reg) => Some(registers[reg as usize]),
+            Expr::Add(ref l, ref r) => l.eval(registers)?.checked_add(r.eval(registers)?),
+            Expr::Mul(ref l, ref r) => l.eval(registers)?.checked_mul(r.eval(registers)?),
+            Expr::Product(ref low, ref high) => {
+                let (low, high) = (low.eval(registers)?, high.eval(registers)?);
+                if high < low {
+                    return Some(1);
+                }
+                if low <= 0 && high >= 0 {
+                    return Some(0);
+                }
+                // at most one factor is 1 or -1, every other one at least
+                // doubles the product, so a long range overflows early on
+                (low..=high).try_fold(1i64, |product, k| product.checked_mul(k))
+            }
+        }
+    }
+
+    /// Whether the formula depends on the initial value of `reg`.
+    pub fn uses(&self, reg: Register) -> bool {
+        match *self {
+            Expr::Const(_) => false,
+            Expr::Reg(r) => r == reg,
+            Expr::Add(ref l, ref r) | Expr::Mul(ref l, ref r) | Expr::Product(ref l, ref r) => {
+                l.uses(reg) || r.uses(reg)
+            }
+        }
+    }
+
+    /// The formula with the registers replaced by `values`.
+    pub fn substitute(&self, values: &[Expr; 4]) -> Expr {
+        match *self {
+            Expr::Const(value) => Expr::Const(value),
+            Expr::Reg(reg) => values[reg as usize].clone(),
+            Expr::Add(ref l, ref r) => l.substitute(values) + r.substitute(values),
+            Expr::Mul(ref l, ref r) => l.substitute(values) * r.substitute(values),
+            Expr::Product(ref low, ref high) => Expr::product(low.substitute(values), high.substitute(values)),
+        }
+    }
+
+    /// `self - other`, if that is a constant.
+    fn difference(&self, other: &Expr) -> Option<i64> {
+        match self.clone() + Expr::Const(-1) * other.clone() {
+            Expr::Const(difference) => Some(difference),
+            _ => None,
+        }
+    }
+
+    /// Whether the formula is positive whatever the initial registers are.
+    fn is_positive(&self) -> bool {
+        match *self {
+            Expr::Const(value) => value > 0,
+            Expr::Reg(_) => false,
+            Expr::Add(ref l, ref r) | Expr::Mul(ref l, ref r) => l.is_positive() && r.is_positive(),
+            Expr::Product(ref low, _) => matches!(**low, Expr::Const(low) if low > 0),
+        }
+    }
+
+    /// The terms of the sum with their coefficients, and its constant.
+    fn linear(&self) -> (Vec<(Expr, i64)>, i64) {
+        match *self {
+            Expr::Const(value) => (Vec::new(), value),
+            Expr::Add(ref l, ref r) => {
+                let (mut terms, constant) = l.linear();
+                let (more, other) = r.linear();
+                terms.extend(more);
+                (terms, constant.wrapping_add(other))
+            }
+            Expr::Mul(ref l, ref r) => match **l {
+                Expr::Const(k) => {
+                    let (terms, constant) = r.linear();
+                    (terms.into_iter().map(|(t, c)| (t, c.wrapping_mul(k))).collect(), constant.wrapping_mul(k))
+                }
+                _ => (vec![(self.clone(), 1)], 0),
+            },
+            _ => (vec![(self.clone(), 1)], 0),
+        }
+    }
+
+    fn from_linear(terms: Vec<(Expr, i64)>, constant: i64) -> Expr {
+        let mut combined: Vec<(Expr, i64)> = Vec::new();
+        for (term, k) in terms {
+            match combined.iter_mut().find(|t| t.0 == term) {
+                Some(t) => t.1 = t.1.wrapping_add(k),
+                None => combined.push((term, k)),
+            }
+        }
+        combined.retain(|t| t.1 != 0);
+        combined.sort_by(|x, y| (x.1 < 0, &x.0).cmp(&(y.1 < 0, &y.0)));
+        let sum = combined.into_iter()
+            .map(|(term, k)| match k {
+                1 => term,
+                k => Expr::Mul(Box::new(Expr::Const(k)), Box::new(term)),
+            })
+            .fold(None, |sum, term| Some(match sum {
+                Some(sum) => Expr::Add(Box::new(sum), Box::new(term)),
+                None => term,
+            }));
+        match (sum, constant) {
+            (None, constant) => Expr::Const(constant),
+            (Some(sum), 0) => sum,
+            (Some(sum), constant) => Expr::Add(Box::new(sum), Box::new(Expr::Const(constant))),
+        }
+    }
+
+    /// The constant factor of the product and its other factors.
+    fn factors(&self) -> (i64, Vec<Expr>) {
+        match *self {
+            Expr::Const(value) => (value, Vec::new()),
+            Expr::Mul(ref l, ref r) => {
+                let (constant, mut factors) = l.factors();
+                let (other, more) = r.factors();
+                factors.extend(more);
+                (constant.wrapping_mul(other), factors)
+            }
+            _ => (1, vec![self.clone()]),
+        }
+    }
+}
+
+/// The product `low..=high` extended by `factor` on either end.
+fn extend_product(low: &Expr, high: &Expr, factor: &Expr) -> Option<Expr> {
+    match *factor {
+        Expr::Product(ref l, ref h) if l.difference(high) == Some(1) => {
+            Some(Expr::product(low.clone(), (**h).clone()))
+        }
+        _ if factor.difference(high) == Some(1) => Some(Expr::product(low.clone(), factor.clone())),
+        _ if low.difference(factor) == Some(1) => Some(Expr::product(factor.clone(), high.clone())),
+        _ => None,
+    }
+}
+
+/// Merges the factors continuing a product into it.
+fn merge_products(constant: &mut i64, factors: &mut Vec<Expr>) {
+    'merge: loop {
+        for i in 0..factors.len() {
+            let (low, high) = match factors[i] {
+                Expr::Product(ref low, ref high) => ((**low).clone(), (**high).clone()),
+                _ => continue,
+            };
+            for j in (0..factors.len()).filter(|&j| j != i) {
+                if let Some(product) = extend_product(&low, &high, &factors[j]) {
+                    factors[i] = product;
+                    factors.remove(j);
+                    continue 'merge;
+                }
+            }
+        }
+        break;
+    }
+    // 5 * 4 * 3 * 2 * product(6..=a) is a!
+    for factor in factors.iter_mut() {
+        if let Expr::Product(ref mut low, _) = *factor {
+            if let Expr::Const(ref mut low) = **low {
+                while *low > 2 && *constant % (*low - 1) == 0 {
+                    *constant /= *low - 1;
+                    *low -= 1;
+                }
+            }
+        }
+    }
+}
+
+impl Add for Expr {
+    type Output = Expr;
+
+    fn add(self, other: Expr) -> Expr {
+        let (mut terms, constant) = self.linear();
+        let (more, other) = other.linear();
+        terms.extend(more);
+        Expr::from_linear(terms, constant.wrapping_add(other))
+    }
+}
+
+/// A constant times a sum is multiplied out, products of consecutive
+/// integers are merged.
+impl Mul for Expr {
+    type Output = Expr;
+
+    fn mul(self, other: Expr) -> Expr {
+        let (constant, mut factors) = self.factors();
+        let (other, more) = other.factors();
+        factors.extend(more);
+        let mut constant = constant.wrapping_mul(other);
+        if constant == 0 || factors.is_empty() {
+            return Expr::Const(constant);
+        }
+        if let [Expr::Add(_, _)] = factors[..] {
+            let (terms, sum) = factors[0].linear();
+            let terms = terms.into_iter().map(|(t, k)| (t, k.wrapping_mul(constant))).collect();
+            return Expr::from_linear(terms, sum.wrapping_mul(constant));
+        }
+        merge_products(&mut constant, &mut factors);
+        factors.sort();
+        let mut factors = factors.into_iter();
+        let first = factors.next().expect("merging keeps a factor");
+        let product = factors.fold(first, |product, factor| Expr::Mul(Box::new(product), Box::new(factor)));
+        match constant {
+            1 => product,
+            constant => Expr::Mul(Box::new(Expr::Const(constant)), Box::new(product)),
+        }
+    }
+}
+
+/// Writes `e` as a factor of a product.
+fn write_factor(f: &mut Formatter, e: &Expr) -> fmt::Result {
+    match *e {
+        Expr::Add(_, _) => write!(f, "({})", e),
+        _ => write!(f, "{}", e),
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Expr::Const(value) => write!(f, "{}", value),
+            Expr::Reg(reg) => write!(f, "{}", reg),
+            Expr::Add(ref l, ref r) => {
+                write!(f, "{}", l)?;
+                match **r {
+                    Expr::Const(value) if value < 0 => write!(f, " - {}", -i128::from(value)),
+                    Expr::Mul(ref k, ref term) => match **k {
+                        Expr::Const(-1) => {
+                            write!(f, " - ")?;
+                            write_factor(f, term)
+                        }
+                        Expr::Const(k) if k < 0 => {
+                            write!(f, " - {} * ", -i128::from(k))?;
+                            write_factor(f, term)
+                        }
+                        _ => write!(f, " + {}", r),
+                    },
+                    _ => write!(f, " + {}", r),
+                }
+            }
+            Expr::Mul(ref l, ref r) => {
+                match **l {
+                    Expr::Const(-1) => write!(f, "-")?,
+                    ref l => {
+                        write_factor(f, l)?;
+                        write!(f, " * ")?;
+                    }
+                }
+                write_factor(f, r)
+            }
+            Expr::Product(ref low, ref high) => match (&**low, &**high) {
+                (&Expr::Const(1), &Expr::Const(_)) | (&Expr::Const(1), &Expr::Reg(_)) |
+                (&Expr::Const(2), &Expr::Const(_)) | (&Expr::Const(2), &Expr::Reg(_)) => write!(f, "{}!", high),
+                (&Expr::Const(1), _) | (&Expr::Const(2), _) => write!(f, "({})!", high),
+                _ => write!(f, "product({}..={})", low, high),
+            },
+        }
+    }
+}
+
+/// A fact about the initial registers the path through the program depends on.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Assumption {
+    Zero(Expr),
+    NonZero(Expr),
+    Positive(Expr),
+    Equals(Expr, i64),
+}
+
+impl Assumption {
+    fn expr(&self) -> &Expr {
+        match *self {
+            Assumption::Zero(ref e) | Assumption::NonZero(ref e) |
+            Assumption::Positive(ref e) | Assumption::Equals(ref e, _) => e,
+        }
+    }
+
+    fn substitute(&self, values: &[Expr; 4]) -> Assumption {
+        match *self {
+            Assumption::Zero(ref e) => Assumption::Zero(e.substitute(values)),
+            Assumption::NonZero(ref e) => Assumption::NonZero(e.substitute(values)),
+            Assumption::Positive(ref e) => Assumption::Positive(e.substitute(values)),
+            Assumption::Equals(ref e, value) => Assumption::Equals(e.substitute(values), value),
+        }
+    }
+
+    /// Whether the assumption holds whatever the initial registers are.
+    fn is_trivial(&self) -> bool {
+        match *self {
+            Assumption::Positive(ref e) => e.is_const() || e.is_positive(),
+            _ => self.expr().is_const(),
+        }
+    }
+
+    /// The register and its smallest value, if this is a lower bound of a single register.
+    fn lower_bound(&self) -> Option<(Register, i64)> {
+        let e = match *self {
+            Assumption::Positive(ref e) => e,
+            _ => return None,
+        };
+        let (terms, constant) = e.linear();
+        match terms[..] {
+            // k * reg + constant > 0 if reg > -constant / k
+            [(Expr::Reg(reg), k)] if k > 0 => Some((reg, constant.checked_neg()?.div_euclid(k).checked_add(1)?)),
+            _ => None,
+        }
+    }
+
+    /// The value the assumption gives `reg`, if it pins it to a constant.
+    fn pinned(&self, reg: Register) -> Option<i64> {
+        let (e, value) = match *self {
+            Assumption::Zero(ref e) => (e, 0),
+            Assumption::Equals(ref e, value) => (e, value),
+            _ => return None,
+        };
+        let (terms, constant) = e.linear();
+        match terms[..] {
+            [(Expr::Reg(r), k)] if r == reg => {
+                let value = value.checked_sub(constant)?;
+                if value.checked_rem(k)? == 0 {
+                    value.checked_div(k)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Display for Assumption {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Assumption::Zero(ref e) => write!(f, "{} == 0", e),
+            Assumption::NonZero(ref e) => write!(f, "{} != 0", e),
+            Assumption::Positive(ref e) => write!(f, "{} > 0", e),
+            Assumption::Equals(ref e, value) => write!(f, "{} == {}", e, value),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Value {
+    concrete: i64,
+    expr: Expr,
+}
+
+/// The formulas of the registers at a loop header, each its own register.
+fn header_registers() -> [Expr; 4] {
+    [Expr::Reg(Register::A), Expr::Reg(Register::B), Expr::Reg(Register::C), Expr::Reg(Register::D)]
+}
+
+/// What one iteration of a loop does, over the registers at the loop header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Iteration {
+    registers: [Expr; 4],
+    assumptions: Vec<Assumption>,
+    outputs: Vec<Expr>,
+    /// The positions of the instructions toggled.
+    toggles: Vec<usize>,
+}
+
+impl Iteration {
+    fn new() -> Iteration {
+        Iteration { registers: header_registers(), assumptions: Vec::new(), outputs: Vec::new(), toggles: Vec::new() }
+    }
+}
+
+/// A loop run one iteration at a time.
+#[derive(Clone, Debug)]
+struct LoopState {
+    header: usize,
+    body: BTreeSet<usize>,
+    /// The registers when `run` started.
+    start: [Value; 4],
+    /// Identical iterations in a row, and how many of them ran.
+    run: Option<(Iteration, u64)>,
+    /// The concrete registers when the current iteration started.
+    arrival: [i64; 4],
+    /// The current iteration, its registers are filled in once it ends.
+    current: Iteration,
+}
+
+/// The header and the positions in the body of every loop in `code`.
+///
+/// Jumps by a register set to a constant earlier in the block count as jumps
+/// by that constant, see `Cfg::known_offsets`. This only decides where
+/// iterations start, the jumps themselves are taken by their concrete offset.
+fn find_loops(code: &[Instruction]) -> Vec<(usize, BTreeSet<usize>)> {
+    let offsets = Cfg::build(code).known_offsets();
+    let resolved: Vec<Instruction> = code.iter().zip(offsets)
+        .map(|(instruction, offset)| match (instruction, offset) {
+            (&Instruction::Jnz(ref condition, FromLocation::Reg(_)), Some(offset)) => {
+                Instruction::Jnz(condition.clone(), FromLocation::Int(offset))
+            }
+            (instruction, _) => instruction.clone(),
+        })
+        .collect();
+    let cfg = Cfg::build(&resolved);
+    cfg.loops().into_iter()
+        .map(|l| {
+            let body = l.blocks.iter().flat_map(|&b| cfg.blocks[b].start..cfg.blocks[b].end).collect();
+            (cfg.blocks[l.header].start, body)
+        })
+        .collect()
+}
+
+/// The factor the iteration multiplies `reg` with, if it does nothing else to it.
+fn accumulator_factor(iteration: &Expr, reg: Register) -> Option<Expr> {
+    let (constant, mut factors) = iteration.factors();
+    let position = factors.iter().position(|f| *f == Expr::Reg(reg))?;
+    factors.remove(position);
+    match (constant, factors.len()) {
+        (1, 1) => factors.pop(),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SymbolicMachine {
+    registers: [Value; 4],
+    pc: i64,
+    code: Vec<Instruction>,
+    ops: Vec<Option<Op>>,
+    loops: Vec<(usize, BTreeSet<usize>)>,
+    looping: Option<LoopState>,
+    assumptions: Vec<Assumption>,
+    assumed: HashSet<Assumption>,
+    outputs: Vec<Expr>,
+}
+
+impl SymbolicMachine {
+    /// A machine with every register set to the constant 0.
+    pub fn new(code: Vec<Instruction>) -> SymbolicMachine {
+        let zero = Value { concrete: 0, expr: Expr::Const(0) };
+        SymbolicMachine {
+            registers: [zero.clone(), zero.clone(), zero.clone(), zero],
+            pc: 0,
+            ops: optimizer::optimize(&code),
+            loops: find_loops(&code),
+            code,
+            looping: None,
+            assumptions: Vec::new(),
+            assumed: HashSet::new(),
+            outputs: Vec::new(),
+        }
+    }
+
+    /// Makes `reg` an unknown, `value` is only used to decide the path.
+    pub fn symbolic(mut self, reg: Register, value: i64) -> SymbolicMachine {
+        self.registers[reg as usize] = Value { concrete: value, expr: Expr::Reg(reg) };
+        self
+    }
+
+    pub fn constant(mut self, reg: Register, value: i64) -> SymbolicMachine {
+        self.registers[reg as usize] = Value { concrete: value, expr: Expr::Const(value) };
+        self
+    }
+
+    pub fn get_reg(&self, reg: Register) -> &Expr {
+        &self.registers[reg as usize].expr
+    }
+
+    /// The concrete value of `reg` on the path taken.
+    pub fn get_concrete(&self, reg: Register) -> i64 {
+        self.registers[reg as usize].concrete
+    }
+
+    /// The assumptions the formulas depend on, in the order they were made.
+    ///
+    /// Of the lower bounds of a register only the strongest one is kept.
+    pub fn assumptions(&self) -> &[Assumption] {
+        &self.assumptions
+    }
+
+    /// The formulas of the values output so far.
+    pub fn outputs(&self) -> &[Expr] {
+        &self.outputs
+    }
+
+    fn value(&self, location: &FromLocation) -> Value {
+        match *location {
+            FromLocation::Int(value) => Value { concrete: i64::from(value), expr: Expr::Const(i64::from(value)) },
+            FromLocation::Reg(reg) => self.registers[reg as usize].clone(),
+        }
+    }
+
+    fn concrete_registers(&self) -> [i64; 4] {
+        [self.registers[0].concrete, self.registers[1].concrete,
+         self.registers[2].concrete, self.registers[3].concrete]
+    }
+
+    fn expr_registers(&self) -> [Expr; 4] {
+        [self.registers[0].expr.clone(), self.registers[1].expr.clone(),
+         self.registers[2].expr.clone(), self.registers[3].expr.clone()]
+    }
+
+    /// Makes an assumption, part of the current iteration if a loop runs.
+    fn assume(&mut self, assumption: Assumption) {
+        match self.looping {
+            Some(ref mut l) => {
+                if !assumption.is_trivial() && !l.current.assumptions.contains(&assumption) {
+                    l.current.assumptions.push(assumption);
+                }
+            }
+            None => self.record(assumption),
+        }
+    }
+
+    /// Adds an assumption over the initial registers.
+    fn record(&mut self, assumption: Assumption) {
+        if assumption.is_trivial() {
+            return;
+        }
+        if let Some((reg, min)) = assumption.lower_bound() {
+            let assumption = Assumption::Positive(Expr::Reg(reg) + Expr::Const(1 - min));
+            let existing = self.assumptions.iter()
+                .position(|a| a.lower_bound().map(|(r, _)| r) == Some(reg));
+            if let Some(k) = existing {
+                if self.assumptions[k].lower_bound().map(|(_, m)| m) < Some(min) {
+                    self.assumed.remove(&self.assumptions[k]);
+                    self.assumed.insert(assumption.clone());
+                    self.assumptions[k] = assumption;
+                }
+                return;
+            }
+            if self.assumed.insert(assumption.clone()) {
+                self.assumptions.push(assumption);
+            }
+            return;
+        }
+        if self.assumed.insert(assumption.clone()) {
+            self.assumptions.push(assumption);
+        }
+    }
+
+    fn output(&mut self, expr: Expr) {
+        match self.looping {
+            Some(ref mut l) => l.current.outputs.push(expr),
+            None => self.outputs.push(expr),
+        }
+    }
+
+    /// The offset given by `location`, assuming its formula keeps the value.
+    fn offset(&mut self, location: &FromLocation) -> i64 {
+        let value = self.value(location);
+        self.assume(Assumption::Equals(value.expr, value.concrete));
+        value.concrete
+    }
+
+    fn halted(&self) -> bool {
+        self.pc < 0 || self.pc >= self.code.len() as i64
+    }
+
+    /// Runs until the program ends, returns `false` if it needs more than `max_steps`.
+    ///
+    /// A collapsed loop counts as one step.
+    pub fn run(&mut self, max_steps: u64) -> bool {
+        for _ in 0..max_steps {
+            if self.halted() {
+                return true;
+            }
+            self.execute();
+        }
+        self.leave_loop();
+        self.halted()
+    }
+
+    fn execute(&mut self) {
+        self.step();
+        let state = self.looping.as_ref().map(|l| (l.header, self.pc >= 0 && l.body.contains(&(self.pc as usize))));
+        match state {
+            Some((header, _)) if self.pc == header as i64 => self.next_iteration(),
+            Some((_, false)) => self.leave_loop(),
+            _ => (),
+        }
+    }
+
+    fn step(&mut self) {
+        let pc = self.pc as usize;
+        if let Some(op) = self.ops[pc].clone() {
+            if self.execute_op(&op) {
+                self.pc += op.slots() as i64;
+                return;
+            }
+        }
+        if self.looping.is_none() {
+            if let Some((_, body)) = self.loops.iter().find(|l| l.0 == pc) {
+                let body = body.clone();
+                self.enter_loop(pc, body);
+            }
+        }
+        match self.code[pc].clone() {
+            Instruction::Inc(reg) => self.update(reg, 1),
+            Instruction::Dec(reg) => self.update(reg, -1),
+            Instruction::Cpy(from, dst) => self.registers[dst as usize] = self.value(&from),
+            Instruction::Jnz(condition, offset) => {
+                let condition = self.value(&condition);
+                if condition.concrete != 0 {
+                    self.assume(Assumption::NonZero(condition.expr));
+                    self.pc = self.pc.saturating_add(self.offset(&offset));
+                    return;
+                }
+                self.assume(Assumption::Zero(condition.expr));
+            }
+            Instruction::Tgl(offset) => self.toggle(&offset),
+            Instruction::Out(value) => {
+                let value = self.value(&value);
+                self.output(value.expr);
+            }
+            Instruction::Invalid(_) => (),
+            Instruction::Extension(extension) => {
+                let mut context = Context { machine: self, jump: None };
+                extension.opcode().execute(extension.operands(), &mut context);
+                if let Some(target) = context.jump {
+                    self.pc = target;
+                    return;
+                }
+            }
+        }
+        self.pc += 1;
+    }
+
+    /// Toggles the instruction `location` away.
+    ///
+    /// A target in the program is assumed to stay the same, a target outside
+    /// of it to stay outside on the same side.
+    fn toggle(&mut self, location: &FromLocation) {
+        let value = self.value(location);
+        let position = self.pc.saturating_add(value.concrete);
+        let len = self.code.len() as i64;
+        if position >= len {
+            self.assume(Assumption::Positive(value.expr + Expr::Const(self.pc - len + 1)));
+        } else if position < 0 {
+            self.assume(Assumption::Positive(Expr::Const(-1) * value.expr + Expr::Const(-self.pc)));
+        } else {
+            self.assume(Assumption::Equals(value.expr, value.concrete));
+            let position = position as usize;
+            self.code[position] = self.code[position].clone().toggle();
+            optimizer::reoptimize(&self.code, &mut self.ops, position);
+            self.loops = find_loops(&self.code);
+            if let Some(ref mut l) = self.looping {
+                l.current.toggles.push(position);
+            }
+        }
+    }
+
+    fn update(&mut self, reg: Register, delta: i64) {
+        let value = &mut self.registers[reg as usize];
+        value.concrete = value.concrete.wrapping_add(delta);
+        value.expr = value.expr.clone() + Expr::Const(delta);
+    }
+
+    /// Starts running the loop at `header` one iteration at a time.
+    fn enter_loop(&mut self, header: usize, body: BTreeSet<usize>) {
+        self.looping = Some(LoopState {
+            header,
+            body,
+            start: self.registers.clone(),
+            run: None,
+            arrival: self.concrete_registers(),
+            current: Iteration::new(),
+        });
+        for (value, expr) in self.registers.iter_mut().zip(header_registers().iter()) {
+            value.expr = expr.clone();
+        }
+    }
+
+    /// Ends the current iteration.
+    fn end_iteration(&mut self) -> Option<(LoopState, Iteration)> {
+        let mut l = self.looping.take()?;
+        let mut iteration = mem::replace(&mut l.current, Iteration::new());
+        iteration.registers = self.expr_registers();
+        Some((l, iteration))
+    }
+
+    /// Starts the next iteration once the header is reached again.
+    fn next_iteration(&mut self) {
+        let (mut l, iteration) = self.end_iteration().expect("a loop runs");
+        l.run = match l.run.take() {
+            Some((run, count)) if run == iteration && run.toggles.is_empty() => Some((run, count + 1)),
+            Some((run, count)) => {
+                let start = self.close(&l.start, &run, count, &iteration);
+                for (k, expr) in start.iter().enumerate() {
+                    l.start[k] = Value { concrete: l.arrival[k], expr: expr.clone() };
+                }
+                Some((iteration, 1))
+            }
+            None => Some((iteration, 1)),
+        };
+        l.arrival = self.concrete_registers();
+        self.looping = Some(l);
+        for (value, expr) in self.registers.iter_mut().zip(header_registers().iter()) {
+            value.expr = expr.clone();
+        }
+    }
+
+    /// Stops running a loop, after its current iteration left it or the step limit was reached.
+    fn leave_loop(&mut self) {
+        let (l, iteration) = match self.end_iteration() {
+            Some(ended) => ended,
+            None => return,
+        };
+        let start = match l.run {
+            Some((ref run, count)) => self.close(&l.start, run, count, &iteration),
+            None => [l.start[0].expr.clone(), l.start[1].expr.clone(),
+                     l.start[2].expr.clone(), l.start[3].expr.clone()],
+        };
+        let registers = self.compose(&start, &iteration, 1);
+        for (value, expr) in self.registers.iter_mut().zip(registers.iter()) {
+            value.expr = expr.clone();
+        }
+    }
+
+    /// The registers after `count` iterations of `run` from `start`, with
+    /// `next` the iteration after them.
+    fn close(&mut self, start: &[Value; 4], run: &Iteration, count: u64, next: &Iteration) -> [Expr; 4] {
+        match SymbolicMachine::summarise(start, run, count, next) {
+            Some((registers, assumptions)) => {
+                for assumption in assumptions {
+                    self.record(assumption);
+                }
+                registers
+            }
+            None => {
+                let start = [start[0].expr.clone(), start[1].expr.clone(),
+                             start[2].expr.clone(), start[3].expr.clone()];
+                self.compose(&start, run, count)
+            }
+        }
+    }
+
+    /// The registers after `count` iterations from `start`, one at a time.
+    fn compose(&mut self, start: &[Expr; 4], iteration: &Iteration, count: u64) -> [Expr; 4] {
+        let mut registers = start.clone();
+        for _ in 0..count {
+            for assumption in &iteration.assumptions {
+                self.record(assumption.substitute(&registers));
+            }
+            for output in &iteration.outputs {
+                self.outputs.push(output.substitute(&registers));
+            }
+            registers = [iteration.registers[0].substitute(&registers), iteration.registers[1].substitute(&registers),
+                         iteration.registers[2].substitute(&registers), iteration.registers[3].substitute(&registers)];
+        }
+        registers
+    }
+
+    /// The registers and assumptions after any number of iterations of
+    /// `run`, if the iteration after them pins an induction register.
+    fn summarise(start: &[Value; 4], run: &Iteration, count: u64, next: &Iteration)
+                 -> Option<([Expr; 4], Vec<Assumption>)> {
+        if !run.outputs.is_empty() || !run.toggles.is_empty() {
+            return None;
+        }
+        Register::ALL.iter().filter_map(|&reg| {
+            let step = match run.registers[reg as usize].difference(&Expr::Reg(reg)) {
+                Some(step) if step == 1 || step == -1 => step,
+                _ => return None,
+            };
+            let end = next.assumptions.iter().filter_map(|a| a.pinned(reg)).next()?;
+            if end.checked_sub(start[reg as usize].concrete)?.checked_mul(step)? != count as i64 {
+                return None;
+            }
+            SymbolicMachine::summarise_by(start, run, reg, step, end)
+        }).next()
+    }
+
+    /// Summarises `run`, which steps `induction` by `step` until it is `end`.
+    fn summarise_by(start: &[Value; 4], run: &Iteration, induction: Register, step: i64, end: i64)
+                    -> Option<([Expr; 4], Vec<Assumption>)> {
+        let r = induction as usize;
+        let invariant: Vec<bool> = (0..4).map(|k| run.registers[k] == header_registers()[k]).collect();
+        let known = |e: &Expr| Register::ALL.iter().all(|&q| !e.uses(q) || q == induction || invariant[q as usize]);
+        let count = (Expr::Const(end) + Expr::Const(-1) * start[r].expr.clone()) * Expr::Const(step);
+
+        // the registers the assumptions may use in the first and last iteration
+        let first = [start[0].expr.clone(), start[1].expr.clone(), start[2].expr.clone(), start[3].expr.clone()];
+        let first_concrete = [start[0].concrete, start[1].concrete, start[2].concrete, start[3].concrete];
+        let mut last = first.clone();
+        last[r] = Expr::Const(end - step);
+        let mut last_concrete = first_concrete;
+        last_concrete[r] = end - step;
+
+        let mut registers = first.clone();
+        let mut factors: [Option<Expr>; 4] = [None, None, None, None];
+        for (q, &reg) in Register::ALL.iter().enumerate().filter(|&(q, _)| q != r && !invariant[q]) {
+            let iteration = &run.registers[q];
+            let increment = iteration.clone() + Expr::Const(-1) * Expr::Reg(reg);
+            registers[q] = if known(iteration) {
+                // set from the registers which are known in the last iteration
+                iteration.substitute(&last)
+            } else if known(&increment) && !increment.uses(induction) {
+                first[q].clone() + count.clone() * increment.substitute(&first)
+            } else {
+                let factor = accumulator_factor(iteration, reg)?;
+                let k = factor.difference(&Expr::Reg(induction))?;
+                let (low, high) = if step < 0 {
+                    (Expr::Const(end + 1 + k), first[r].clone() + Expr::Const(k))
+                } else {
+                    (first[r].clone() + Expr::Const(k), Expr::Const(end - 1 + k))
+                };
+                factors[q] = Some(factor);
+                first[q].clone() * Expr::product(low, high)
+            };
+        }
+        registers[r] = Expr::Const(end);
+
+        // an assumption linear in the induction register holds in between if
+        // it holds in the first and the last iteration
+        let mut assumptions = vec![Assumption::Positive(count)];
+        for assumption in &run.assumptions {
+            let e = assumption.expr();
+            if known(e) && !e.uses(induction) {
+                assumptions.push(assumption.substitute(&first));
+            } else if known(e) {
+                let linear = e.linear().0.iter()
+                    .all(|(term, _)| !term.uses(induction) || *term == Expr::Reg(induction));
+                let sign = match (e.eval(&first_concrete)?, e.eval(&last_concrete)?) {
+                    (f, l) if f > 0 && l > 0 => 1,
+                    (f, l) if f < 0 && l < 0 => -1,
+                    _ => return None,
+                };
+                match *assumption {
+                    Assumption::Positive(_) | Assumption::NonZero(_) if linear => {
+                        for values in &[&first, &last] {
+                            assumptions.push(Assumption::Positive(Expr::Const(sign) * e.substitute(values)));
+                        }
+                    }
+                    _ => return None,
+                }
+            } else {
+                // an accumulator stays positive, or non-zero, if its factors are positive
+                let factor = match (assumption, e) {
+                    (&Assumption::Positive(_), &Expr::Reg(q)) | (&Assumption::NonZero(_), &Expr::Reg(q)) => {
+                        factors[q as usize].as_ref()?
+                    }
+                    _ => return None,
+                };
+                if factor.eval(&first_concrete)? <= 0 || factor.eval(&last_concrete)? <= 0 {
+                    return None;
+                }
+                assumptions.push(assumption.substitute(&first));
+                for values in &[&first, &last] {
+                    assumptions.push(Assumption::Positive(factor.substitute(values)));
+                }
+            }
+        }
+        Some((registers, assumptions))
+    }
+
+    /// Collapses a loop, returns `false` if it has to be run step by step.
+    fn execute_op(&mut self, op: &Op) -> bool {
+        let zero = Value { concrete: 0, expr: Expr::Const(0) };
+        match *op {
+            Op::Add { dst, src } => {
+                let count = self.registers[src as usize].clone();
+                if count.concrete <= 0 {
+                    return false;
+                }
+                self.assume(Assumption::Positive(count.expr.clone()));
+                let sum = &mut self.registers[dst as usize];
+                sum.concrete = sum.concrete.wrapping_add(count.concrete);
+                sum.expr = sum.expr.clone() + count.expr;
+                self.registers[src as usize] = zero;
+            }
+            Op::Mul { dst, ref factor, inner, outer } => {
+                let factor = self.value(factor);
+                let count = self.registers[outer as usize].clone();
+                if factor.concrete <= 0 || count.concrete <= 0 {
+                    return false;
+                }
+                self.assume(Assumption::Positive(factor.expr.clone()));
+                self.assume(Assumption::Positive(count.expr.clone()));
+                let sum = &mut self.registers[dst as usize];
+                sum.concrete = sum.concrete.wrapping_add(factor.concrete.wrapping_mul(count.concrete));
+                sum.expr = sum.expr.clone() + factor.expr * count.expr;
+                self.registers[inner as usize] = zero.clone();
+                self.registers[outer as usize] = zero;
+            }
+        }
+        true
+    }
+}
+
+/// A symbolic machine executing an extension, see `Opcode::execute`.
+///
+/// Like the built-in instructions, decisions are made on the concrete values
+/// and the assumptions are recorded.
+struct Context<'a> {
+    machine: &'a mut SymbolicMachine,
+    /// The target of a jump taken by the extension.
+    jump: Option<i64>,
+}
+
+impl<'a> Context<'a> {
+    fn update(&mut self, dst: Register, concrete: i64, expr: Expr) {
+        self.machine.registers[dst as usize] = Value { concrete, expr };
+    }
+}
+
+impl<'a> Cpu for Context<'a> {
+    fn value(&mut self, location: &FromLocation) -> i64 {
+        self.machine.offset(location)
+    }
+
+    fn is_zero(&mut self, location: &FromLocation) -> bool {
+        let value = self.machine.value(location);
+        if value.concrete == 0 {
+            self.machine.assume(Assumption::Zero(value.expr));
+            true
+        } else {
+            self.machine.assume(Assumption::NonZero(value.expr));
+            false
+        }
+    }
+
+    fn copy(&mut self, src: &FromLocation, dst: Register) {
+        self.machine.registers[dst as usize] = self.machine.value(src);
+    }
+
+    fn add(&mut self, dst: Register, value: &FromLocation) {
+        let (old, value) = (self.machine.registers[dst as usize].clone(), self.machine.value(value));
+        self.update(dst, old.concrete.wrapping_add(value.concrete), old.expr + value.expr);
+    }
+
+    fn sub(&mut self, dst: Register, value: &FromLocation) {
+        let (old, value) = (self.machine.registers[dst as usize].clone(), self.machine.value(value));
+        self.update(dst, old.concrete.wrapping_sub(value.concrete), old.expr + Expr::Const(-1) * value.expr);
+    }
+
+    fn mul(&mut self, dst: Register, value: &FromLocation) {
+        let (old, value) = (self.machine.registers[dst as usize].clone(), self.machine.value(value));
+        self.update(dst, old.concrete.wrapping_mul(value.concrete), old.expr * value.expr);
+    }
+
+    fn jump(&mut self, offset: &FromLocation) {
+        let offset = self.machine.offset(offset);
+        self.jump = Some(self.machine.pc.saturating_add(offset));
+    }
+
+    fn toggle(&mut self, offset: &FromLocation) {
+        self.machine.toggle(offset);
+    }
+
+    fn output(&mut self, value: &FromLocation) {
+        let value = self.machine.value(value);
+        self.machine.output(value.expr);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use machine::{Halt, Machine, Outcome};
+    use parse::parse_program;
+
+    fn a() -> Expr {
+        Expr::Reg(Register::A)
+    }
+
+    fn b() -> Expr {
+        Expr::Reg(Register::B)
+    }
+
+    #[test]
+    fn simplify() {
+        assert_eq!(a() + Expr::Const(1) + Expr::Const(-1), a());
+        assert_eq!(Expr::Const(2) * (Expr::Const(3) * b()), Expr::Const(6) * b());
+        assert_eq!((a() * Expr::Const(0)) + b(), b());
+        assert_eq!((a() + Expr::Const(-1)).to_string(), "a - 1");
+        assert_eq!((Expr::Const(2) * (a() + Expr::Const(-2)) * b()).to_string(), "b * (2 * a - 4)");
+    }
+
+    #[test]
+    fn linear_form() {
+        let sum = a() + Expr::Const(-2) + a() + Expr::Const(-2);
+        assert_eq!(sum.to_string(), "2 * a - 4");
+        assert_eq!(b() + a() + Expr::Const(-1) * b(), a());
+        assert_eq!((b() + Expr::Const(-3) * a()).to_string(), "b - 3 * a");
+        assert_eq!(a() * b() + b() * a(), Expr::Const(2) * (b() * a()));
+    }
+
+    #[test]
+    fn products() {
+        let factorial = Expr::product(Expr::Const(1), a());
+        assert_eq!(factorial.to_string(), "a!");
+        assert_eq!(factorial.eval(&[5, 0, 0, 0]), Some(120));
+        assert_eq!(factorial.eval(&[20, 0, 0, 0]), Some(2432902008176640000));
+        assert_eq!(factorial.eval(&[i64::MAX, 0, 0, 0]), None);
+        assert_eq!(Expr::product(a(), b()).eval(&[i64::MIN, i64::MAX, 0, 0]), Some(0));
+        assert_eq!(Expr::product(a(), b()).eval(&[-3, -1, 0, 0]), Some(-6));
+        assert_eq!(Expr::product(a(), b()).eval(&[2, 1, 0, 0]), Some(1));
+        assert_eq!(Expr::product(Expr::Const(3), Expr::Const(5)), Expr::Const(60));
+        let tail = Expr::product(Expr::Const(6), a() + Expr::Const(-1));
+        assert_eq!(tail.to_string(), "product(6..=a - 1)");
+        assert_eq!(Expr::Const(120) * tail * a(), factorial);
+    }
+
+    #[test]
+    fn loops_closed_by_register_jumps() {
+        let code = parse_program(include_str!("../../23/input.txt")).unwrap();
+        let loops = find_loops(&code);
+        // the factorial loop, closed by `jnz 1 c` after `cpy -16 c`
+        assert_eq!(loops[0].0, 2);
+        assert_eq!(loops[0].1, (2..19).collect());
+    }
+
+    #[test]
+    fn collapses_loops() {
+        let input = "cpy b c\ninc a\ndec c\njnz c -2\ndec d\njnz d -5\ninc a";
+        let mut machine = SymbolicMachine::new(parse_program(input).unwrap())
+            .symbolic(Register::A, 1)
+            .symbolic(Register::B, 2)
+            .symbolic(Register::D, 3);
+        assert!(machine.run(100));
+        assert_eq!(machine.get_reg(Register::A).to_string(), "a + b * d + 1");
+        assert_eq!(machine.get_reg(Register::D), &Expr::Const(0));
+        let assumptions: Vec<_> = machine.assumptions().iter().map(|a| a.to_string()).collect();
+        assert_eq!(assumptions, vec!["b > 0", "d > 0"]);
+    }
+
+    #[test]
+    fn summarises_loops() {
+        // a = a!, the multiplication is collapsed, the outer loop counts b down
+        let input = "cpy a b\ncpy 1 a\n\
+                     cpy a d\ncpy 0 a\ncpy b c\ninc a\ndec c\njnz c -2\ndec d\njnz d -5\n\
+                     dec b\njnz b -9";
+        let code = parse_program(input).unwrap();
+        for n in 2..10 {
+            let mut machine = SymbolicMachine::new(code.clone()).symbolic(Register::A, n);
+            assert!(machine.run(1000));
+            assert_eq!(machine.get_reg(Register::A).to_string(), "a!");
+            assert_eq!(machine.get_reg(Register::B), &Expr::Const(0));
+            let assumptions: Vec<_> = machine.assumptions().iter().map(|a| a.to_string()).collect();
+            assert_eq!(assumptions, vec!["a - 1 > 0"]);
+        }
+    }
+
+    #[test]
+    fn branch_assumptions() {
+        let input = "jnz c 2\ninc a\ninc a";
+        let mut machine = SymbolicMachine::new(parse_program(input).unwrap()).symbolic(Register::C, 0);
+        assert!(machine.run(100));
+        assert_eq!(machine.get_reg(Register::A), &Expr::Const(2));
+        assert_eq!(machine.assumptions(), &[Assumption::Zero(Expr::Reg(Register::C))]);
+    }
+
+    #[test]
+    fn assumption_bounds() {
+        let negated = Expr::Const(-1) * a();
+        assert_eq!(Assumption::Equals(negated.clone(), 5).pinned(Register::A), Some(-5));
+        assert_eq!(Assumption::Equals(negated, i64::MIN).pinned(Register::A), None);
+        assert_eq!(Assumption::Positive(a() + Expr::Const(-3)).lower_bound(), Some((Register::A, 4)));
+        assert_eq!(Assumption::Positive(a() + Expr::Const(i64::MIN + 1)).lower_bound(), None);
+    }
+
+    #[test]
+    fn step_limit() {
+        let mut machine = SymbolicMachine::new(parse_program("jnz 1 0").unwrap());
+        assert!(!machine.run(100));
+    }
+
+    #[test]
+    fn matches_machine() {
+        let code = parse_program(include_str!("../../23/input.txt")).unwrap();
+        // smaller values make the program toggle its own jumps into endless loops
+        for a in 6..12 {
+            let mut symbolic = SymbolicMachine::new(code.clone()).symbolic(Register::A, a);
+            assert!(symbolic.run(100_000));
+            let mut machine = Machine::<i64>::from_code(code.clone());
+            *machine.get_reg_mut(Register::A) = a;
+            machine.enable_optimizer();
+            assert_eq!(machine.run_with_limit(100_000), Outcome::Halted(Halt::EndOfProgram));
+            let expected = machine.get_reg(Register::A);
+            assert_eq!(symbolic.get_concrete(Register::A), expected);
+            assert_eq!(symbolic.get_reg(Register::A).eval(&[a, 0, 0, 0]), Some(expected));
+        }
+    }
+
+    #[test]
+    fn puzzle23_formula() {
+        let code = parse_program(include_str!("../../23/input.txt")).unwrap();
+        for &a in &[7, 12] {
+            let mut machine = SymbolicMachine::new(code.clone()).symbolic(Register::A, a);
+            assert!(machine.run(100_000));
+            assert_eq!(machine.get_reg(Register::A).to_string(), "a! + 6474");
+            assert_eq!(machine.assumptions(), &[Assumption::Positive(Expr::Reg(Register::A) + Expr::Const(-6))]);
+        }
+    }
+}
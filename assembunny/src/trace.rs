@@ -0,0 +1,243 @@
+//! Execution traces of a `Machine`.
+//!
+//! A trace starts with a header holding the initial registers and whether the
+//! optimizer was enabled, followed by one line per executed step. Instructions
+//! are written in assembunny syntax, fused ops of the optimizer are prefixed
+//! with `fused`:
+//!
+//! ```text
+//! start a=7 b=0 c=0 d=0 optimizer=on
+//! 0 cpy a b b:0->7
+//! 4 fused mul a b c d a:0->42 c:6->0 d:7->0
+//! 16 tgl c toggle 18: jnz 1 c -> cpy 1 c
+//! ```
+
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use instruction::{Instruction, Register};
+use machine::Machine;
+use optimizer::Op;
+use word::Word;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Executed {
+    Instruction(Instruction),
+    /// A fused op of the optimizer which replaced several instructions.
+    Op(Op),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Toggle {
+    pub position: usize,
+    pub old: Instruction,
+    pub new: Instruction,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Step<W = i32> {
+    pub pc: usize,
+    pub executed: Executed,
+    /// Registers whose value changed, with their old and new value.
+    pub changes: Vec<(Register, W, W)>,
+    pub toggle: Option<Toggle>,
+}
+
+impl<W: Word> Display for Step<W> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} ", self.pc)?;
+        match self.executed {
+            Executed::Instruction(ref instruction) => write!(f, "{}", instruction)?,
+            Executed::Op(ref op) => write!(f, "{}", op)?,
+        }
+        for &(reg, old, new) in &self.changes {
+            write!(f, " {}:{}->{}", reg, old, new)?;
+        }
+        if let Some(ref toggle) = self.toggle {
+            write!(f, " toggle {}: {} -> {}", toggle.position, toggle.old, toggle.new)?;
+        }
+        Ok(())
+    }
+}
+
+/// The first line of a trace, holding the registers of `machine` and whether
+/// its optimizer is enabled.
+pub fn header<W: Word>(machine: &Machine<W>) -> String {
+    let registers: Vec<_> = Register::ALL.iter()
+        .map(|&reg| format!("{}={}", reg, machine.get_reg(reg)))
+        .collect();
+    let optimizer = if machine.optimizer_enabled() { "on" } else { "off" };
+    format!("start {} optimizer={}", registers.join(" "), optimizer)
+}
+
+/// Sets the registers of `machine` to the ones stored in a trace header and
+/// enables or disables its optimizer accordingly.
+pub fn apply_header<W: Word>(machine: &mut Machine<W>, header: &str) -> Result<(), String> {
+    let mut parts = header.split_whitespace();
+    if parts.next() != Some("start") {
+        return Err(format!("Invalid trace header: {}", header));
+    }
+    let mut optimizer = None;
+    for part in parts {
+        let mut assignment = part.splitn(2, '=');
+        let name = assignment.next().unwrap();
+        let value = assignment.next().ok_or_else(|| format!("Invalid trace header field: {}", part))?;
+        if name == "optimizer" {
+            optimizer = match value {
+                "on" => Some(true),
+                "off" => Some(false),
+                _ => return Err(format!("Invalid optimizer mode: {}", part)),
+            };
+            continue;
+        }
+        let reg: Register = name.parse().map_err(|e| format!("{}", e))?;
+        *machine.get_reg_mut(reg) = value.parse().map_err(|_| format!("Invalid register value: {}", part))?;
+    }
+    match optimizer {
+        Some(true) => machine.enable_optimizer(),
+        Some(false) => machine.disable_optimizer(),
+        None => return Err(format!("Trace header doesn't say whether the optimizer was on: {}", header)),
+    }
+    Ok(())
+}
+
+/// The first step where a program and a trace disagree.
+///
+/// `expected` is `None` if the trace ended before the program halted,
+/// `actual` is `None` if the program halted before the trace ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub step: usize,
+    pub expected: Option<String>,
+    pub actual: Option<String>,
+}
+
+/// Re-runs the trace `lines` on `machine` and reports the first divergence.
+pub fn replay<'a, W, I>(machine: &mut Machine<W>, lines: I) -> Result<Option<Divergence>, String>
+    where W: Word, I: IntoIterator<Item = &'a str>
+{
+    let mut lines = lines.into_iter();
+    apply_header(machine, lines.next().ok_or("Empty trace")?)?;
+    machine.enable_trace();
+
+    let mut running = machine.halt_reason().is_none();
+    for step in 0.. {
+        let actual = if running {
+            running = machine.execute().is_some();
+            machine.take_trace().pop().map(|s| s.to_string())
+        } else {
+            None
+        };
+        let expected = lines.next().map(|s| s.to_string());
+        if expected.is_none() && actual.is_none() {
+            break;
+        }
+        if expected != actual {
+            return Ok(Some(Divergence { step, expected, actual }));
+        }
+    }
+    Ok(None)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parse::parse_program;
+
+    fn traced(input: &str, a: i32) -> (String, Vec<String>) {
+        let mut machine = Machine::new(parse_program(input).unwrap());
+        *machine.get_reg_mut(Register::A) = a;
+        machine.enable_trace();
+        let header = header(&machine);
+        machine.run();
+        (header, machine.take_trace().iter().map(|s| s.to_string()).collect())
+    }
+
+    #[test]
+    fn records_register_changes() {
+        let (header, steps) = traced("cpy 41 a\ninc b\njnz a 2\ndec a", 1);
+        assert_eq!(header, "start a=1 b=0 c=0 d=0 optimizer=off");
+        assert_eq!(steps, vec![
+            "0 cpy 41 a a:1->41",
+            "1 inc b b:0->1",
+            "2 jnz a 2",
+        ]);
+    }
+
+    #[test]
+    fn records_toggle() {
+        let (_, steps) = traced("tgl 1\ninc a", 0);
+        assert_eq!(steps, vec![
+            "0 tgl 1 toggle 1: inc a -> dec a",
+            "1 dec a a:0->-1",
+        ]);
+        let (_, steps) = traced("tgl 1\njnz 1 2", 0);
+        assert_eq!(steps, vec!["0 tgl 1 toggle 1: jnz 1 2 -> invalid cpy 1 2", "1 invalid cpy 1 2"]);
+    }
+
+    #[test]
+    fn records_fused_op() {
+        let mut machine = Machine::new(parse_program("cpy 2 b\ninc a\ndec b\njnz b -2").unwrap());
+        machine.enable_optimizer();
+        machine.enable_trace();
+        machine.run();
+        let steps: Vec<_> = machine.take_trace().iter().map(|s| s.to_string()).collect();
+        assert_eq!(steps[1], "1 fused add a b a:0->2 b:2->0");
+    }
+
+    #[test]
+    fn header_records_optimizer() {
+        let code = parse_program("cpy 2 b\ninc a\ndec b\njnz b -2").unwrap();
+        let mut optimized = Machine::new(code.clone());
+        optimized.enable_optimizer();
+        optimized.enable_trace();
+        let header = header(&optimized);
+        assert_eq!(header, "start a=0 b=0 c=0 d=0 optimizer=on");
+        optimized.run();
+        let steps: Vec<String> = optimized.take_trace().iter().map(|s| s.to_string()).collect();
+        let lines = Some(header.as_str()).into_iter().chain(steps.iter().map(|s| s.as_str()));
+        let mut machine = Machine::new(code);
+        assert_eq!(replay(&mut machine, lines), Ok(None));
+        assert!(machine.optimizer_enabled());
+        assert!(apply_header(&mut machine, "start a=0 b=0 c=0 d=0 optimizer=off").is_ok());
+        assert!(!machine.optimizer_enabled());
+        assert!(apply_header(&mut machine, "start a=0 b=0 c=0 d=0").is_err());
+    }
+
+    #[test]
+    fn replay_matches() {
+        let input = "cpy 3 b\ninc a\ndec b\njnz b -2";
+        let (header, steps) = traced(input, 5);
+        let lines: Vec<&str> = Some(header.as_str()).into_iter()
+            .chain(steps.iter().map(|s| s.as_str())).collect();
+        let mut machine = Machine::new(parse_program(input).unwrap());
+        assert_eq!(replay(&mut machine, lines), Ok(None));
+    }
+
+    #[test]
+    fn replay_reports_first_divergence() {
+        let (header, steps) = traced("inc a\ninc a\ninc b", 0);
+        let lines: Vec<&str> = Some(header.as_str()).into_iter()
+            .chain(steps.iter().map(|s| s.as_str())).collect();
+        let mut machine = Machine::new(parse_program("inc a\ndec a\ninc b").unwrap());
+        assert_eq!(replay(&mut machine, lines), Ok(Some(Divergence {
+            step: 1,
+            expected: Some("1 inc a a:1->2".into()),
+            actual: Some("1 dec a a:1->0".into()),
+        })));
+    }
+
+    #[test]
+    fn replay_reports_early_halt() {
+        let (header, steps) = traced("inc a\ninc a", 0);
+        let lines: Vec<&str> = Some(header.as_str()).into_iter()
+            .chain(steps.iter().map(|s| s.as_str())).collect();
+        let mut machine = Machine::new(parse_program("inc a").unwrap());
+        assert_eq!(replay(&mut machine, lines), Ok(Some(Divergence {
+            step: 1,
+            expected: Some("1 inc a a:1->2".into()),
+            actual: None,
+        })));
+    }
+}
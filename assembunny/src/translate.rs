@@ -0,0 +1,146 @@
+//! Translates programs into Rust source, meant to be called from a build script.
+//!
+//! The generated function dispatches on pc in a `loop`/`match`, with one arm
+//! per instruction. Arithmetic wraps like it does in `Machine`. Programs using
+//! `tgl` can't be translated, since they change their own code, and neither
+//! can programs using `out` or opcodes defined outside of this crate.
+
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter, Write};
+
+use instruction::{FromLocation, Instruction, Register};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TranslateError {
+    /// The program contains a `tgl` (or an instruction only `tgl` produces).
+    SelfModifying { position: usize },
+    /// The program contains an `out`, the generated function has nowhere to send it.
+    Output { position: usize },
+    /// The program contains an instruction of an opcode this crate doesn't know.
+    Extension { position: usize },
+}
+
+impl Display for TranslateError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            TranslateError::SelfModifying { position } => {
+                write!(f, "instruction {} modifies the program, which can't be translated", position)
+            }
+            TranslateError::Output { position } => {
+                write!(f, "instruction {} outputs a value, which can't be translated", position)
+            }
+            TranslateError::Extension { position } => {
+                write!(f, "instruction {} is an extension, which can't be translated", position)
+            }
+        }
+    }
+}
+
+impl Error for TranslateError {}
+
+fn value(location: &FromLocation) -> String {
+    match *location {
+        FromLocation::Int(value) => value.to_string(),
+        FromLocation::Reg(reg) => reg.to_string(),
+    }
+}
+
+/// The statement a jump by `offset` from `position` turns into.
+fn jump(position: usize, offset: &FromLocation) -> String {
+    match *offset {
+        FromLocation::Int(offset) => format!("pc = {}; continue;", position as i64 + i64::from(offset)),
+        FromLocation::Reg(reg) => format!("pc += i64::from({}); continue;", reg),
+    }
+}
+
+/// A function `pub fn <name>(registers: [i32; 4]) -> [i32; 4]` running `code`.
+///
+/// It takes the initial registers a to d and returns them once the program ends.
+pub fn translate(name: &str, code: &[Instruction]) -> Result<String, TranslateError> {
+    let mut out = String::new();
+    writeln!(out, "// Generated by assembunny::translate, do not edit.").unwrap();
+    writeln!(out, "#[allow(unused_mut, unused_variables, clippy::all)]").unwrap();
+    writeln!(out, "pub fn {}(registers: [i32; 4]) -> [i32; 4] {{", name).unwrap();
+    writeln!(out, "    let [mut a, mut b, mut c, mut d] = registers;").unwrap();
+    writeln!(out, "    let mut pc: i64 = 0;").unwrap();
+    writeln!(out, "    loop {{").unwrap();
+    writeln!(out, "        match pc {{").unwrap();
+    for (position, instruction) in code.iter().enumerate() {
+        let statement = match *instruction {
+            Instruction::Inc(reg) => format!("{} = {}.wrapping_add(1);", reg, reg),
+            Instruction::Dec(reg) => format!("{} = {}.wrapping_sub(1);", reg, reg),
+            Instruction::Cpy(FromLocation::Reg(src), dst) if src == dst => String::new(),
+            Instruction::Cpy(ref src, dst) => format!("{} = {};", dst, value(src)),
+            Instruction::Jnz(FromLocation::Int(0), _) => String::new(),
+            Instruction::Jnz(FromLocation::Int(_), ref offset) => jump(position, offset),
+            Instruction::Jnz(FromLocation::Reg(reg), ref offset) => {
+                format!("if {} != 0 {{ {} }}", reg, jump(position, offset))
+            }
+            Instruction::Tgl(_) | Instruction::Invalid(_) => {
+                return Err(TranslateError::SelfModifying { position });
+            }
+            Instruction::Out(_) => return Err(TranslateError::Output { position }),
+            Instruction::Extension(_) => return Err(TranslateError::Extension { position }),
+        };
+        writeln!(out, "            // {}", instruction).unwrap();
+        writeln!(out, "            {} => {{ {} }}", position, statement).unwrap();
+    }
+    writeln!(out, "            _ => break,").unwrap();
+    writeln!(out, "        }}").unwrap();
+    writeln!(out, "        pc += 1;").unwrap();
+    writeln!(out, "    }}").unwrap();
+    let registers: Vec<_> = Register::ALL.iter().map(|reg| reg.to_string()).collect();
+    writeln!(out, "    [{}]", registers.join(", ")).unwrap();
+    writeln!(out, "}}").unwrap();
+    Ok(out)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parse::parse_program;
+
+    #[test]
+    fn translate_program() {
+        let code = parse_program("cpy 2 b\ninc a\ndec b\njnz b -2\njnz 1 c").unwrap();
+        assert_eq!(translate("double", &code), Ok("\
+// Generated by assembunny::translate, do not edit.
+#[allow(unused_mut, unused_variables, clippy::all)]
+pub fn double(registers: [i32; 4]) -> [i32; 4] {
+    let [mut a, mut b, mut c, mut d] = registers;
+    let mut pc: i64 = 0;
+    loop {
+        match pc {
+            // cpy 2 b
+            0 => { b = 2; }
+            // inc a
+            1 => { a = a.wrapping_add(1); }
+            // dec b
+            2 => { b = b.wrapping_sub(1); }
+            // jnz b -2
+            3 => { if b != 0 { pc = 1; continue; } }
+            // jnz 1 c
+            4 => { pc += i64::from(c); continue; }
+            _ => break,
+        }
+        pc += 1;
+    }
+    [a, b, c, d]
+}
+".to_string()));
+    }
+
+    #[test]
+    fn refuses_tgl() {
+        let code = parse_program("inc a\ntgl a").unwrap();
+        assert_eq!(translate("toggling", &code), Err(TranslateError::SelfModifying { position: 1 }));
+    }
+
+    #[test]
+    fn refuses_out() {
+        let code = parse_program("out a").unwrap();
+        assert_eq!(translate("clock", &code), Err(TranslateError::Output { position: 0 }));
+    }
+}
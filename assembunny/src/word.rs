@@ -0,0 +1,73 @@
+//! Integer types usable as registers of a `Machine`.
+
+use std::fmt::{Debug, Display};
+use std::hash::Hash;
+use std::str::FromStr;
+
+pub trait Word: Copy + Default + Debug + Display + FromStr + Ord + Hash {
+    fn from_i32(value: i32) -> Self;
+
+    /// The value as a jump or toggle offset, saturated to the `i64` range.
+    fn to_offset(self) -> i64;
+
+    fn checked_add(self, other: Self) -> Option<Self>;
+    fn checked_sub(self, other: Self) -> Option<Self>;
+    fn checked_mul(self, other: Self) -> Option<Self>;
+    fn wrapping_add(self, other: Self) -> Self;
+    fn wrapping_sub(self, other: Self) -> Self;
+    fn wrapping_mul(self, other: Self) -> Self;
+}
+
+macro_rules! impl_word {
+    ($($t:ty),*) => {$(
+        impl Word for $t {
+            fn from_i32(value: i32) -> Self {
+                value as $t
+            }
+
+            #[allow(clippy::unnecessary_cast)]
+            fn to_offset(self) -> i64 {
+                (self as i128).max(i64::MIN as i128).min(i64::MAX as i128) as i64
+            }
+
+            fn checked_add(self, other: Self) -> Option<Self> {
+                <$t>::checked_add(self, other)
+            }
+
+            fn checked_sub(self, other: Self) -> Option<Self> {
+                <$t>::checked_sub(self, other)
+            }
+
+            fn checked_mul(self, other: Self) -> Option<Self> {
+                <$t>::checked_mul(self, other)
+            }
+
+            fn wrapping_add(self, other: Self) -> Self {
+                <$t>::wrapping_add(self, other)
+            }
+
+            fn wrapping_sub(self, other: Self) -> Self {
+                <$t>::wrapping_sub(self, other)
+            }
+
+            fn wrapping_mul(self, other: Self) -> Self {
+                <$t>::wrapping_mul(self, other)
+            }
+        }
+    )*}
+}
+
+impl_word!(i32, i64, i128);
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn offset_saturates() {
+        assert_eq!(Word::to_offset(-5i32), -5);
+        assert_eq!(Word::to_offset(i128::MAX), i64::MAX);
+        assert_eq!(Word::to_offset(i128::MIN), i64::MIN);
+    }
+}
@@ -0,0 +1,182 @@
+//! Command line handling shared by the puzzle binaries.
+//!
+//! Every binary accepts `[--part 1|2] [--<param> <value>]... [input]`. The
+//! input is a path, or `-` for stdin, and defaults to the input bundled with
+//! the puzzle. Without `--part` both parts are run.
+
+extern crate solution;
+
+use std::env;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::process;
+use std::str::FromStr;
+
+use solution::Puzzle;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Args {
+    part: Option<u32>,
+    params: Vec<(String, String)>,
+    input: Option<String>,
+}
+
+impl Args {
+    /// Parses the arguments following the program name.
+    ///
+    /// `params` are the options the puzzle accepts besides `--part`, without
+    /// the leading dashes.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I, params: &[&str]) -> Result<Args, String> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if let Some(name) = arg.strip_prefix("--") {
+                if name != "part" && !params.contains(&name) {
+                    return Err(format!("Unknown option {}", arg));
+                }
+                let value = args.next().ok_or_else(|| format!("Missing value for {}", arg))?;
+                if name == "part" {
+                    parsed.part = match value.as_str() {
+                        "1" => Some(1),
+                        "2" => Some(2),
+                        _ => return Err(format!("Invalid part {}, expected 1 or 2", value)),
+                    };
+                } else {
+                    parsed.params.push((name.to_string(), value));
+                }
+            } else if parsed.input.is_none() {
+                parsed.input = Some(arg);
+            } else {
+                return Err(format!("Unexpected argument {}", arg));
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Parses the command line, exiting with a usage message if it's invalid.
+    pub fn from_env(params: &[&str]) -> Args {
+        let mut args = env::args();
+        let program = args.next().unwrap_or_default();
+        Args::parse(args, params).unwrap_or_else(|e| {
+            let params: String = params.iter().map(|p| format!(" [--{} <value>]", p)).collect();
+            eprintln!("{}", e);
+            eprintln!("usage: {} [--part 1|2]{} [input|-]", program, params);
+            process::exit(2);
+        })
+    }
+
+    /// The part selected with `--part`.
+    pub fn part(&self) -> Option<u32> {
+        self.part
+    }
+
+    /// The input path given on the command line, `-` stands for stdin.
+    pub fn input_path(&self) -> Option<&str> {
+        self.input.as_deref()
+    }
+
+    /// Whether `part` should be run.
+    pub fn runs(&self, part: u32) -> bool {
+        self.part.is_none_or(|p| p == part)
+    }
+
+    /// The value of `--<name>` if it was given, the last one wins.
+    pub fn try_param<T: FromStr>(&self, name: &str) -> Result<Option<T>, String> {
+        match self.params.iter().rev().find(|(n, _)| n == name) {
+            Some((_, value)) => {
+                value.parse().map(Some).map_err(|_| format!("Invalid value for --{}: {}", name, value))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Like `try_param`, but exits if the value doesn't parse.
+    pub fn param<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.try_param(name).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(2);
+        })
+    }
+
+    /// Reads the input file, or stdin for `-`, falling back to `bundled`.
+    pub fn read_input(&self, bundled: &str) -> io::Result<String> {
+        let mut input = String::new();
+        match self.input.as_deref() {
+            None => input.push_str(bundled),
+            Some("-") => {
+                io::stdin().read_to_string(&mut input)?;
+            }
+            Some(path) => {
+                File::open(path)?.read_to_string(&mut input)?;
+            }
+        }
+        Ok(input)
+    }
+
+    /// Like `read_input`, but exits if the input can't be read.
+    pub fn input(&self, bundled: &str) -> String {
+        self.read_input(bundled).unwrap_or_else(|e| {
+            eprintln!("Could not read {}: {}", self.input.as_ref().unwrap(), e);
+            process::exit(1);
+        })
+    }
+
+    /// Solves the selected parts of `puzzle` on the input, printing
+    /// `part one: <answer>` and so on, exits if a part fails.
+    pub fn run(&self, puzzle: &dyn Puzzle, bundled: &str) {
+        let input = self.input(bundled);
+        for (part, name) in (1..puzzle.parts() + 1).zip(&["one", "two"]).filter(|&(part, _)| self.runs(part)) {
+            match puzzle.solve(&input, part) {
+                Ok(answer) => println!("part {}: {}", name, answer),
+                Err(e) => {
+                    eprintln!("part {}: {}", name, e);
+                    process::exit(1);
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|a| a.to_string()), &["a"])
+    }
+
+    #[test]
+    fn defaults() {
+        let args = parse(&[]).unwrap();
+        assert!(args.runs(1) && args.runs(2));
+        assert_eq!(args.param::<i32>("a"), None);
+        assert_eq!(args.read_input("bundled").unwrap(), "bundled");
+    }
+
+    #[test]
+    fn options() {
+        let args = parse(&["--part", "2", "--a", "3", "input.txt", "--a", "12"]).unwrap();
+        assert_eq!(args.part(), Some(2));
+        assert!(!args.runs(1) && args.runs(2));
+        assert_eq!(args.param("a"), Some(12));
+        assert_eq!(args.input, Some("input.txt".to_string()));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(parse(&["--part", "3"]).is_err());
+        assert!(parse(&["--part"]).is_err());
+        assert!(parse(&["--b", "1"]).is_err());
+        assert!(parse(&["one", "two"]).is_err());
+        assert!(parse(&["--a", "x"]).unwrap().try_param::<i32>("a").is_err());
+        assert!(parse(&["missing.txt"]).unwrap().read_input("bundled").is_err());
+    }
+
+    #[test]
+    fn read_file() {
+        let args = parse(&["Cargo.toml"]).unwrap();
+        assert!(args.read_input("bundled").unwrap().contains("name = \"cli\""));
+    }
+}
@@ -0,0 +1,88 @@
+//! The interface every day implements, so runners, benchmarks and answer
+//! checkers can treat all days alike.
+
+use std::error;
+use std::fmt::Display;
+
+pub type Error = Box<dyn error::Error>;
+
+/// The solution of a day, holding the parameters of the puzzle if it has any.
+pub trait Solution {
+    /// The input after parsing, shared by both parts.
+    type Input;
+    type Answer1: Display;
+    type Answer2: Display;
+
+    /// The number of parts solved, `part2` is only called if this is 2.
+    const PARTS: u32 = 2;
+
+    fn parse(&self, input: &str) -> Result<Self::Input, Error>;
+
+    fn part1(&self, input: &Self::Input) -> Result<Self::Answer1, Error>;
+
+    fn part2(&self, input: &Self::Input) -> Result<Self::Answer2, Error>;
+}
+
+/// A `Solution` with its types erased, so solutions of different days can be
+/// kept in one list.
+pub trait Puzzle {
+    fn parts(&self) -> u32;
+
+    /// Parses `input` and solves `part`, returning the answer as text.
+    fn solve(&self, input: &str, part: u32) -> Result<String, Error>;
+}
+
+impl<S: Solution> Puzzle for S {
+    fn parts(&self) -> u32 {
+        S::PARTS
+    }
+
+    fn solve(&self, input: &str, part: u32) -> Result<String, Error> {
+        if part < 1 || part > S::PARTS {
+            return Err(format!("There is no part {}", part).into());
+        }
+        let input = self.parse(input)?;
+        if part == 1 {
+            self.part1(&input).map(|answer| answer.to_string())
+        } else {
+            self.part2(&input).map(|answer| answer.to_string())
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Sums the numbers in part one, multiplies them in part two.
+    struct Numbers;
+
+    impl Solution for Numbers {
+        type Input = Vec<i32>;
+        type Answer1 = i32;
+        type Answer2 = i32;
+
+        fn parse(&self, input: &str) -> Result<Vec<i32>, Error> {
+            input.split_whitespace().map(|n| n.parse().map_err(Error::from)).collect()
+        }
+
+        fn part1(&self, input: &Vec<i32>) -> Result<i32, Error> {
+            Ok(input.iter().sum())
+        }
+
+        fn part2(&self, input: &Vec<i32>) -> Result<i32, Error> {
+            Ok(input.iter().product())
+        }
+    }
+
+    #[test]
+    fn solve() {
+        let puzzles: Vec<Box<dyn Puzzle>> = vec![Box::new(Numbers)];
+        assert_eq!(puzzles[0].parts(), 2);
+        assert_eq!(puzzles[0].solve("2 3 4", 1).unwrap(), "9");
+        assert_eq!(puzzles[0].solve("2 3 4", 2).unwrap(), "24");
+        assert!(puzzles[0].solve("2 x", 1).is_err());
+        assert!(puzzles[0].solve("2 3 4", 3).is_err());
+    }
+}